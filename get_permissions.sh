@@ -13,5 +13,34 @@ chgrp -R msr /dev/cpu/*/msr
 chmod g+r /dev/cpu/*/msr
 
 cpufreq-set -f 4G
-echo 1 | tee /sys/devices/system/cpu/cpu3/online
-echo 0 | tee /sys/devices/system/cpu/cpu{1,2,4,5,6,7,8,9,10,11}/online
+
+# Keep the benchmark and housekeeping CPUs online and take all others offline, which includes the
+# SMT siblings of the benchmark CPUs. Use the same lists as `power run --bench-cpus --housekeeping-cpus`.
+BENCH_CPUS=${BENCH_CPUS:-3}
+HOUSEKEEPING_CPUS=${HOUSEKEEPING_CPUS:-0}
+
+expand() {
+    local IFS=,
+    for part in $1; do
+        if [[ $part == *-* ]]; then seq "${part%-*}" "${part#*-}"; else echo "$part"; fi
+    done
+}
+
+keep=" $(expand "$BENCH_CPUS" | tr '\n' ' ') $(expand "$HOUSEKEEPING_CPUS" | tr '\n' ' ') "
+for online in /sys/devices/system/cpu/cpu[0-9]*/online; do
+    cpu=$(basename "$(dirname "$online")")
+    if [[ $keep == *" ${cpu#cpu} "* ]]; then echo 1 > "$online"; fi
+done
+for cpu in $(expand "$BENCH_CPUS"); do
+    for sibling in $(expand "$(cat /sys/devices/system/cpu/cpu$cpu/topology/thread_siblings_list)"); do
+        if [[ " $(expand "$HOUSEKEEPING_CPUS" | tr '\n' ' ') " == *" $sibling "* ]]; then
+            echo "Housekeeping CPU $sibling is a SMT sibling of benchmark CPU $cpu" >&2
+            exit 1
+        fi
+    done
+done
+for online in /sys/devices/system/cpu/cpu[0-9]*/online; do
+    cpu=$(basename "$(dirname "$online")")
+    if [[ $keep != *" ${cpu#cpu} "* ]]; then echo 0 > "$online"; fi
+done
+echo "Online CPUs: $(cat /sys/devices/system/cpu/online)"
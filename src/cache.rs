@@ -0,0 +1,292 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::BenchSettings;
+use crate::data::project::{get_workdir_for_project, Project};
+use crate::execute::Failure;
+use crate::journal::{BenchCommand, RunSettings};
+use crate::manifest::ProjectRevision;
+
+/// Executables of a run are kept in `cache/<run id>/<key digest>/`
+pub const CACHE_DIR: &str = "cache";
+
+/// Everything that determines the executable of a bench file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheKey {
+    pub project: String,
+    pub bench_file: String,
+    pub commit: Option<String>,
+    pub dirty: Option<bool>,
+    pub features: Vec<String>,
+    /// `rustc -vV` in the project, which follows its toolchain file
+    pub toolchain: Option<String>,
+    /// `RUSTFLAGS` and bench profile overrides from the environment
+    pub flags: Vec<String>,
+    /// Variables the config sets for the bench file
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+impl CacheKey {
+    pub fn capture(
+        project: &str,
+        bench_file: &str,
+        features: &[String],
+        settings: &BenchSettings,
+    ) -> CacheKey {
+        let revision = ProjectRevision::capture(project);
+        let toolchain = Command::new("rustc")
+            .args(settings.cargo_toolchain())
+            .arg("-vV")
+            .current_dir(get_workdir_for_project(project))
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+        let mut flags = env::vars()
+            .filter(|(name, _)| {
+                name == "RUSTFLAGS"
+                    || name == "CARGO_ENCODED_RUSTFLAGS"
+                    || name.starts_with("CARGO_PROFILE_BENCH_")
+            })
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>();
+        flags.sort();
+
+        CacheKey {
+            project: project.to_string(),
+            bench_file: bench_file.to_string(),
+            commit: revision.commit,
+            dirty: revision.dirty,
+            features: features
+                .iter()
+                .chain(settings.features.iter())
+                .cloned()
+                .collect(),
+            toolchain,
+            flags,
+            env: settings.env.clone(),
+        }
+    }
+
+    pub fn digest(&self) -> String {
+        let digest = Sha256::digest(serde_json::to_string(self).unwrap().as_bytes());
+        hex::encode(&digest[..8])
+    }
+}
+
+/// One cached executable.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub key: CacheKey,
+    pub executable: String,
+    pub sha256: String,
+}
+
+pub fn sha256_file(path: &Path) -> Option<String> {
+    let content = fs::read(path).ok()?;
+    Some(hex::encode(Sha256::digest(&content)))
+}
+
+impl CacheEntry {
+    /// Copy a compiled executable into `dir`.
+    pub fn store(dir: &Path, key: CacheKey, executable: &Path) -> CacheEntry {
+        let entry_dir = dir.join(key.digest());
+        fs::create_dir_all(&entry_dir)
+            .unwrap_or_else(|err| panic!("Could not create cache {:?}: {}", entry_dir, err));
+        let cached = entry_dir.join(executable.file_name().unwrap());
+        // Copies the permissions as well
+        fs::copy(executable, &cached).unwrap_or_else(|err| {
+            panic!("Could not cache {:?} as {:?}: {}", executable, cached, err)
+        });
+        CacheEntry {
+            key,
+            executable: cached.to_string_lossy().to_string(),
+            sha256: sha256_file(&cached).unwrap(),
+        }
+    }
+
+    /// Why the cached executable can not be used, `None` if it can.
+    pub fn problem(&self) -> Option<String> {
+        match sha256_file(Path::new(&self.executable)) {
+            None => Some(format!("{} is missing", self.executable)),
+            Some(sha256) if sha256 != self.sha256 => Some(format!(
+                "{} changed, its hash is {} instead of {}",
+                self.executable, sha256, self.sha256
+            )),
+            Some(_) => None,
+        }
+    }
+}
+
+/// The executables of a run, compiled once and used by every iteration.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExecutableCache {
+    /// One command per benchmark, running a cached executable
+    pub commands: Vec<BenchCommand>,
+    /// Benchmarks whose bench file did not compile
+    pub failures: Vec<Failure>,
+    pub entries: Vec<CacheEntry>,
+}
+
+/// `(project, bench file)` of a command named `project/bench file/benchmark id`
+fn bench_file_of(command: &BenchCommand) -> (String, String) {
+    let mut parts = command.name.splitn(3, '/');
+    (
+        parts.next().unwrap().to_string(),
+        parts.next().unwrap_or_default().to_string(),
+    )
+}
+
+pub fn run_dir(run: &str) -> PathBuf {
+    env::current_dir().unwrap().join(CACHE_DIR).join(run)
+}
+
+/// The key of a bench file as it is compiled now from one of `projects`.
+fn capture_key(
+    projects: &[Project],
+    project: &str,
+    bench_file: &str,
+    settings: &RunSettings,
+) -> CacheKey {
+    let features = projects
+        .iter()
+        .find(|candidate| candidate.name == project)
+        .and_then(|project| project.bench_files.iter().find(|file| file.name == bench_file))
+        .map(|file| file.features.clone())
+        .unwrap_or_default();
+    CacheKey::capture(
+        project,
+        bench_file,
+        &features,
+        &settings.bench_settings(project, bench_file),
+    )
+}
+
+impl ExecutableCache {
+    /// Cache the executables of freshly compiled commands of `projects` for the run.
+    pub fn build(
+        run: &str,
+        projects: &[Project],
+        commands: Vec<BenchCommand>,
+        failures: Vec<Failure>,
+        settings: &RunSettings,
+    ) -> ExecutableCache {
+        let dir = run_dir(run);
+        let mut cache = ExecutableCache {
+            failures,
+            ..Default::default()
+        };
+        for mut command in commands {
+            let (project, bench_file) = bench_file_of(&command);
+            let existing = cache.entries.iter().position(|entry| {
+                entry.key.project == project && entry.key.bench_file == bench_file
+            });
+            let index = match existing {
+                Some(index) => index,
+                None => {
+                    let key = capture_key(projects, &project, &bench_file, settings);
+                    cache.entries.push(CacheEntry::store(
+                        &dir,
+                        key,
+                        Path::new(&command.executable),
+                    ));
+                    cache.entries.len() - 1
+                }
+            };
+            command.executable = cache.entries[index].executable.clone();
+            cache.commands.push(command);
+        }
+        println!("Cached {} executables in {:?}", cache.entries.len(), dir);
+        cache
+    }
+
+    /// Problems of the cached executables, empty if all can be used.
+    pub fn problems(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter_map(CacheEntry::problem)
+            .collect()
+    }
+
+    /// Replace missing or changed executables with freshly compiled ones,
+    /// which is only allowed when they are built from the same inputs.
+    pub fn repair(
+        &mut self,
+        run: &str,
+        projects: &[Project],
+        compiled: &[BenchCommand],
+        settings: &RunSettings,
+    ) {
+        let dir = run_dir(run);
+        for entry in self
+            .entries
+            .iter_mut()
+            .filter(|entry| entry.problem().is_some())
+        {
+            let key = &entry.key;
+            let current = capture_key(projects, &key.project, &key.bench_file, settings);
+            if &current != key {
+                panic!(
+                    "The inputs of {}/{} changed since the run started, start a new run instead. Was {:?}, is {:?}",
+                    key.project, key.bench_file, key, current
+                );
+            }
+            let executable = compiled
+                .iter()
+                .find(|command| {
+                    bench_file_of(command) == (key.project.clone(), key.bench_file.clone())
+                })
+                .unwrap_or_else(|| panic!("Could not recompile {}/{}", key.project, key.bench_file))
+                .executable
+                .clone();
+
+            let repaired = CacheEntry::store(&dir, current, Path::new(&executable));
+            if repaired.sha256 != entry.sha256 {
+                println!(
+                    "Warning: the rebuilt executable of {}/{} differs from the one earlier iterations used",
+                    key.project, key.bench_file
+                );
+            }
+            *entry = repaired;
+        }
+    }
+}
+
+#[test]
+fn test_cache_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let executable = dir.path().join("bench-0123456789abcdef");
+    fs::write(&executable, "\x7fELF").unwrap();
+    let key = CacheKey {
+        project: "project".to_string(),
+        bench_file: "bench".to_string(),
+        commit: Some("0123456789abcdef0123456789abcdef01234567".to_string()),
+        dirty: Some(false),
+        features: vec![],
+        toolchain: None,
+        flags: vec![],
+        env: BTreeMap::new(),
+    };
+    let with_feature = CacheKey {
+        features: vec!["simd".to_string()],
+        ..key.clone()
+    };
+    assert_eq!(key.digest(), key.clone().digest());
+    assert_ne!(key.digest(), with_feature.digest());
+
+    let entry = CacheEntry::store(&dir.path().join("cache"), key, &executable);
+    assert!(entry.executable.contains(&entry.key.digest()));
+    assert_eq!(entry.problem(), None);
+
+    fs::write(&entry.executable, "\x7fELF changed").unwrap();
+    assert!(entry.problem().unwrap().contains("changed"));
+    fs::remove_file(&entry.executable).unwrap();
+    assert!(entry.problem().unwrap().contains("missing"));
+}
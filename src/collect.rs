@@ -1,23 +1,39 @@
+use std::collections::HashMap;
 use std::env;
 use std::fs::{File, OpenOptions};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::string::ToString;
-use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 use byteorder::WriteBytesExt;
 
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use rand::seq::SliceRandom;
-use rand::thread_rng;
 use rstats::Printing;
 use serde::{Deserialize, Serialize};
 use syscalls::Sysno::clone;
 
 use crate::data::compileroutput::CompilerOutputElement;
 use crate::data::project::{
-    BenchFile, get_workdir_for_project, Project, read_target_projects,
+    BenchFile, get_workdir_for_project, Project,
 };
+use crate::data::registry::Registry;
+use crate::cache::ExecutableCache;
+use crate::config::BenchSettings;
+use crate::cpu;
+use crate::cpu::CpuSelection;
+use crate::design::Design;
+use crate::execute::{execute, Failure, FailureKind, LOG_DIR};
+use crate::harness::HarnessKind;
+use crate::journal::{BenchCommand, IterationProgress, Journal, RunSettings};
+use crate::manifest;
+use crate::plan::CompileTimes;
+use crate::manifest::{BenchmarkTiming, Environment, Manifest, ProjectRevision};
+use crate::snapshot::RestoreGuard;
+use crate::store;
+use crate::store::Store;
 
 // Enable setting probes and traces
 // sudo sysctl kernel.perf_event_paranoid=-1 -w
@@ -114,7 +130,24 @@ fn main() {
     // Set debug mode
     env::set_var("ENERGY_DEBUG", "");
     env::set_var("KEEP_PROJECTS", "");
-    run(1, 5, 1, 5, false);
+    let settings = RunSettings {
+        iterations: 1,
+        measurement_time: 5,
+        warmup_time: 1,
+        sample_size: 5,
+        cpus: CpuSelection {
+            bench: cpu::CpuList(vec![3]),
+            housekeeping: cpu::CpuList(vec![0]),
+        },
+        timeout: None,
+        retries: 0,
+        retry_on: vec![],
+        jobs: None,
+        design: Design::Rmit,
+        seed: 0,
+        overrides: Default::default(),
+    };
+    run(settings, None);
 }
 
 fn enable_cores() {
@@ -144,13 +177,22 @@ fn enable_cores() {
 
 }
 
-fn disable_cores() {
-    // Disable all, core 0 will fail
-    println!("{:?}", glob::glob("/sys/devices/system/cpu/cpu*/online"));
-    glob::glob("/sys/devices/system/cpu/cpu*/online").expect("Found no cpu online files").for_each(|entry| {
+fn disable_cores(cpus: &CpuSelection) {
+    let root = Path::new(cpu::SYSFS_CPU);
+    // Needs the topology of the benchmark CPUs, which is gone once they are offline
+    if let Err(err) = cpus.validate(root) {
+        panic!("Invalid CPU selection: {}", err);
+    }
+    let keep = cpus.keep();
+
+    // Disable all others, cores without an `online` file (usually cpu0) can not be disabled
+    glob::glob(&format!("{}/cpu*/online", cpu::SYSFS_CPU)).expect("Found no cpu online files").for_each(|entry| {
         match entry {
             Ok(path) => {
-                if path.to_str().unwrap().contains("cpu3") || path.to_str().unwrap().contains("cpu0") {
+                let cpu = path.parent().unwrap().file_name().unwrap().to_string_lossy()
+                    .trim_start_matches("cpu")
+                    .parse::<usize>();
+                if cpu.map_or(true, |cpu| keep.contains(&cpu)) {
 
                 } else {
                     let mut file = OpenOptions::new().write(true).truncate(true).open(&path).unwrap();
@@ -171,14 +213,24 @@ fn disable_cores() {
         }
     });
 
+    Command::new("cset").args(["set", "-c", &cpus.bench.to_string(), "BENCH"]).status().unwrap();
 
-    Command::new("cset").args(["set", "-c", "3", "BENCH"]).status().unwrap();
-
-
-
+    // Do not start benchmarking on a machine in an unknown state
+    if let Err(err) = cpus.verify(root) {
+        panic!("CPU isolation failed: {}", err);
+    }
 }
 
-fn compile_projects(measurement_time: &u64, warmup_time: &u64, sample_size: &u64) -> Vec<(Command, String)> {
+/// Compile all bench files, returns a command per benchmark and a failure per benchmark that did not compile.
+/// Projects are compiled on up to `settings.jobs` threads, the bench files of one project share a target
+/// directory so those are compiled one after the other. The jobs are split between the threads, so
+/// cargo runs at most `settings.jobs` rustc processes in total.
+fn compile_projects(
+    projects: &[Project],
+    clean: bool,
+    settings: &RunSettings,
+) -> (Vec<BenchCommand>, Vec<Failure>) {
+    let jobs = settings.compile_jobs();
     enable_cores();
     let m = MultiProgress::new();
     let sty = ProgressStyle::with_template(
@@ -188,15 +240,12 @@ fn compile_projects(measurement_time: &u64, warmup_time: &u64, sample_size: &u64
         .progress_chars("##-");
 
     // Clear artifacts
-    let target_projects = read_target_projects();
-
-    if !env::var("KEEP_PROJECTS").is_ok() {
-        let cargo_clear_bar = m.add(ProgressBar::new(target_projects.len() as u64));
+    if clean && !env::var("KEEP_PROJECTS").is_ok() {
+        let cargo_clear_bar = m.add(ProgressBar::new(projects.len() as u64));
         cargo_clear_bar.set_style(sty.clone());
 
         // Clear
-        for record in &target_projects {
-            let project = Project::load(&record.name).expect("Could not load project {");
+        for project in projects {
             cargo_clear_bar.set_message(format!("Clearing project: {}", &project.name));
             cargo_clean_project(&project.name);
             cargo_clear_bar.inc(1);
@@ -206,63 +255,123 @@ fn compile_projects(measurement_time: &u64, warmup_time: &u64, sample_size: &u64
         m.remove(&cargo_clear_bar);
     }
 
-    let mut commands: Vec<(Command, String)> = Default::default();
-
-    let compile_project_bar = m.add(ProgressBar::new(target_projects.len() as u64));
+    let compile_project_bar = m.add(ProgressBar::new(projects.len() as u64));
     compile_project_bar.set_style(sty.clone());
+    compile_project_bar.set_message(format!("Compiling projects with {} jobs", jobs));
     compile_project_bar.tick();
     compile_project_bar.enable_steady_tick(Duration::from_secs(1));
 
-    // Compile all files and give permissions to all executables
-    // Create command per benchmark
-    for record in &target_projects {
-        compile_project_bar.set_message(format!("Compiling project: {}", record.name.trim()));
+    // Every job takes the next project that is not being compiled yet
+    let next = AtomicUsize::new(0);
+    let workers = jobs.max(1).min(projects.len()).max(1);
+    let cargo_jobs = (jobs / workers).max(1).to_string();
+    let mut compiled = thread::scope(|scope| {
+        let workers = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut compiled = vec![];
+                    while let Some(project) = projects.get(next.fetch_add(1, Ordering::SeqCst)) {
+                        compiled.push((project.name.clone(), compile_project(project, settings, &cargo_jobs, &m, &compile_project_bar, &sty)));
+                        compile_project_bar.inc(1);
+                    }
+                    compiled
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    m.remove(&compile_project_bar);
 
-        let target_project = record;
-        let project = Project::load(&target_project.name).unwrap();
+    // In the order of targets.csv, whichever job finished first
+    compiled.sort_by_key(|(name, _)| projects.iter().position(|project| &project.name == name));
+    let mut commands: Vec<BenchCommand> = Default::default();
+    let mut failures = vec![];
+    let mut times = vec![];
+    for (_, (project_commands, project_failures, project_times)) in compiled {
+        commands.extend(project_commands);
+        failures.extend(project_failures);
+        times.extend(project_times);
+    }
+    // For the estimates of `power run --plan`
+    CompileTimes::record(times);
 
-        let bench_group_bar = m.insert_after(
-            &compile_project_bar,
-            ProgressBar::new(project.bench_files.len() as u64),
+    (commands, failures)
+}
+
+/// Compile the bench files of one project into a command per benchmark.
+fn compile_project(project: &Project, settings: &RunSettings, cargo_jobs: &str, m: &MultiProgress, compile_project_bar: &ProgressBar, sty: &ProgressStyle) -> (Vec<BenchCommand>, Vec<Failure>, Vec<(String, f64)>) {
+    let mut commands: Vec<BenchCommand> = Default::default();
+    let mut failures = vec![];
+    let mut times = vec![];
+
+    let bench_group_bar = m.insert_after(
+        compile_project_bar,
+        ProgressBar::new(project.bench_files.len() as u64),
+    );
+    bench_group_bar.set_style(sty.clone());
+    bench_group_bar.tick();
+    bench_group_bar.enable_steady_tick(Duration::from_secs(1));
+    for group in &project.bench_files {
+        let bench_settings = settings.bench_settings(&project.name, &group.name);
+        if bench_settings.exclude {
+            bench_group_bar.inc(1);
+            continue;
+        }
+        bench_group_bar.set_message(format!("Compiling benchmark: {}/{}", project.name.trim(), group.name.trim()));
+
+        // Compile and save the executable
+        let started = Instant::now();
+        let executable = compile_benchmark_file(
+            &group,
+            bench_settings.cargo_toolchain(),
+            Some(vec!["--jobs", cargo_jobs]),
+            Some(bench_settings.features.iter().map(String::as_str).collect()),
+            Some(bench_settings.env.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect()),
         );
-        bench_group_bar.set_style(sty.clone());
-        bench_group_bar.tick();
-        bench_group_bar.enable_steady_tick(Duration::from_secs(1));
-        for group in &project.bench_files {
-            bench_group_bar.set_message(format!("Compiling benchmark: {}", group.name.trim()));
-
-            // Compile and save the executable
-            let executable = compile_benchmark_file(&group, None, None, None, None);
-            let executable = if executable.is_some() { executable.unwrap() } else { continue; };
-
-            let workdir = get_workdir_for_project(&group.project);
-            debugln!("Executable {} and workdir {:?}", &executable, &workdir);
-
-            for benchmark_id in group.benches.iter() {
-                commands.push((
-                    criterion_bench_command(
-                        &executable,
-                        &benchmark_id,
-                        &workdir,
-                        &measurement_time,
-                        &warmup_time,
-                        &sample_size,
-                    ),
-                    format!("{}/{}/{}", project.name, group.name, benchmark_id),
-                ));
+        times.push((format!("{}/{}", project.name, group.name), started.elapsed().as_secs_f64()));
+        let executable = match executable {
+            Some(executable) => executable,
+            None => {
+                println!("Could not compile {} of {}", group.name, project.name);
+                failures.extend(group.runs().iter().map(|benchmark_id| Failure {
+                    name: format!("{}/{}/{}", project.name, group.name, benchmark_id),
+                    attempt: 1,
+                    kind: FailureKind::CompileError,
+                    message: "`cargo bench --no-run` produced no executable".to_string(),
+                    log: None,
+                }));
+                continue;
             }
-            bench_group_bar.inc(1);
+        };
+
+        let workdir = get_workdir_for_project(&group.project);
+        debugln!("Executable {} and workdir {:?}", &executable, &workdir);
+
+        for benchmark_id in group.runs().iter() {
+            commands.push(BenchCommand {
+                name: format!("{}/{}/{}", project.name, group.name, benchmark_id),
+                executable: executable.clone(),
+                benchmark: benchmark_id.clone(),
+                workdir: workdir.clone(),
+                harness: group.harness,
+            });
         }
-        compile_project_bar.inc(1);
-        m.remove(&bench_group_bar);
+        bench_group_bar.inc(1);
     }
-    m.remove(&compile_project_bar);
+    m.remove(&bench_group_bar);
 
-    commands
+    (commands, failures, times)
 }
-pub fn run_project_consecutive(iterations: usize, measurement_time: u64, warmup_time: u64, sample_size: u64) {
+
+pub fn run_project_consecutive(settings: &RunSettings) {
     enable_cores();
-    let commands = compile_projects(&measurement_time, &warmup_time, &sample_size);
+    // Targets that can not be loaded from the registry are left out of the whole run
+    let projects = Registry::open_default().targets();
+    let (commands, mut failures) = compile_projects(&projects, true, settings);
+    let log_dir = Path::new(LOG_DIR).join(chrono::offset::Local::now().timestamp_millis().to_string());
 
     let m = MultiProgress::new();
 
@@ -272,7 +381,9 @@ pub fn run_project_consecutive(iterations: usize, measurement_time: u64, warmup_
         .unwrap();
 
 
-    disable_cores();
+    disable_cores(&settings.cpus);
+    let environment = Environment::capture();
+    let mut timings = vec![];
 
     let command_sty = ProgressStyle::with_template(
         "[{elapsed_precise} | {eta_precise}] {bar:40.cyan/blue} {pos:>3}/{len:3} {msg}",
@@ -284,16 +395,27 @@ pub fn run_project_consecutive(iterations: usize, measurement_time: u64, warmup_
     m.add(command_bar.clone());
     command_bar.tick();
     command_bar.enable_steady_tick(Duration::from_secs(5));
-    for (mut command, name) in commands {
-        let progress_bar = ProgressBar::new(iterations as u64).with_style(sty.clone());
+    for bench in &commands {
+        let progress_bar = ProgressBar::new(settings.iterations as u64).with_style(sty.clone());
         m.add(progress_bar.clone());
         progress_bar.tick();
         command_bar.enable_steady_tick(Duration::from_secs(5));
 
-        (0..iterations).for_each(|_| {
-            run_command(&mut command);
+        let started = manifest::now();
+        let mut success = true;
+        (0..settings.iterations).for_each(|repetition| {
+            let log_dir = log_dir.join(format!("repetition-{}", repetition + 1));
+            let (succeeded, bench_failures) = run_benchmark(bench, settings, &log_dir);
+            success &= succeeded;
+            failures.extend(bench_failures);
             progress_bar.inc(1);
         });
+        timings.push(BenchmarkTiming {
+            name: bench.name.clone(),
+            started,
+            finished: manifest::now(),
+            success,
+        });
         progress_bar.finish_and_clear();
         m.remove(&progress_bar);
         command_bar.inc(1);
@@ -304,36 +426,139 @@ pub fn run_project_consecutive(iterations: usize, measurement_time: u64, warmup_
     
     m.remove(&command_bar);
 
-    let target_projects = read_target_projects();
-
     // Save all data
     let timestamp = chrono::offset::Local::now().timestamp_millis().to_string();
-    for record in &target_projects {
-        let project = Project::load(&record.name).expect("Could not load project");
-        move_data_for_project(project, &timestamp);
+    for project in &projects {
+        move_data_for_project(project.clone(), &timestamp);
+    }
+    Manifest {
+        run: timestamp.clone(),
+        journal: None,
+        iteration: None,
+        rmit: false,
+        settings: settings.clone(),
+        environments: vec![environment],
+        projects: projects
+            .iter()
+            .map(|project| ProjectRevision::capture(&project.name))
+            .collect(),
+        order: commands.into_iter().map(|command| command.name).collect(),
+        benchmarks: timings,
+        failures,
     }
+    .write(&run_dir(&timestamp));
+    store_run(&timestamp);
 }
 
-pub fn run(iterations: usize, measurement_time: u64, warmup_time: u64, sample_size: u64, no_rmit: bool) {
-    if no_rmit {
-        run_project_consecutive(iterations, measurement_time, warmup_time, sample_size)
+pub fn run(settings: RunSettings, resume: Option<String>) {
+    // Puts the cores and cpusets back when the run ends, panics or is interrupted
+    let _guard = RestoreGuard::new();
+
+    if resume.is_none() && settings.design == Design::Consecutive {
+        run_project_consecutive(&settings)
     } else {
         // Default
-        for i in 0..iterations {
+        let mut journal = match resume {
+            Some(id) => {
+                println!("Resuming run {} with the settings it was started with", id);
+                Journal::load(&id)
+            }
+            None => Journal::new(settings),
+        };
+        journal.store();
+        println!(
+            "Progress is kept in {:?}, continue an interrupted run with `power run --resume {}`",
+            Journal::path(&journal.id),
+            journal.id
+        );
+        println!(
+            "Ordering benchmarks with the {} design and seed {}",
+            journal.settings.design, journal.settings.seed
+        );
+
+        for i in 0..journal.settings.iterations {
             println!("Running iteration #{}", i + 1);
-            iteration(&measurement_time, &warmup_time, &sample_size);
+            iteration(&mut journal, i);
         }
     }
 }
 
-fn iteration(measurement_time: &u64, warmup_time: &u64, sample_size: &u64) {
-    enable_cores();
-    let mut commands = compile_projects(measurement_time, warmup_time, sample_size);
+fn iteration(journal: &mut Journal, index: usize) {
+    // Targets that can not be loaded from the registry are left out of the iteration
+    let projects = Registry::open_default().targets();
+
+    // Compile once per run, cleaning only before that first build
+    if journal.cache.is_none() {
+        enable_cores();
+        let (commands, failures) = compile_projects(&projects, true, &journal.settings);
+        journal.cache = Some(ExecutableCache::build(
+            &journal.id,
+            &projects,
+            commands,
+            failures,
+            &journal.settings,
+        ));
+        journal.store();
+
+        let period = journal.settings.design.period(journal.cache.as_ref().unwrap().commands.len());
+        if journal.settings.iterations % period != 0 {
+            println!(
+                "Warning: the {} design is only balanced for a multiple of {} repetitions",
+                journal.settings.design, period
+            );
+        }
+    }
 
-    disable_cores();
+    if journal.iterations.len() <= index {
+        let cache = journal.cache.as_ref().unwrap();
+        let settings = &journal.settings;
+        let order = settings.design.order(&cache.commands, settings.seed, index);
+        journal.iterations.push(IterationProgress {
+            order,
+            failures: cache.failures.clone(),
+            ..Default::default()
+        });
+        journal.store();
+    }
 
-    // Shuffle commands
-    commands.shuffle(&mut thread_rng());
+    if let Some(saved) = &journal.iterations[index].saved {
+        println!("Iteration #{} was already saved to data/{}", index + 1, saved);
+        return;
+    }
+
+    // Every iteration must run the identical executables
+    let problems = journal.cache.as_ref().unwrap().problems();
+    if !problems.is_empty() {
+        println!("Cached executables can not be used, recompiling:\n{}", problems.join("\n"));
+        enable_cores();
+        let (compiled, _) = compile_projects(&projects, false, &journal.settings);
+        let settings = &journal.settings;
+        journal.cache.as_mut().unwrap().repair(&journal.id, &projects, &compiled, settings);
+        journal.store();
+    }
+
+    // Run the cached executables, also in iterations journaled before the cache existed
+    let executables = journal
+        .cache
+        .as_ref()
+        .unwrap()
+        .commands
+        .iter()
+        .map(|command| (command.name.clone(), command.executable.clone()))
+        .collect::<HashMap<String, String>>();
+    for command in journal.iterations[index].order.iter_mut() {
+        if let Some(executable) = executables.get(&command.name) {
+            command.executable = executable.clone();
+        }
+    }
+    journal.store();
+
+    disable_cores(&journal.settings.cpus);
+    journal.iterations[index].environments.push(Environment::capture());
+    journal.store();
+
+    let settings = journal.settings.clone();
+    let commands = journal.iterations[index].remaining();
     let m = MultiProgress::new();
 
     let sty = ProgressStyle::with_template(
@@ -351,30 +576,67 @@ fn iteration(measurement_time: &u64, warmup_time: &u64, sample_size: &u64) {
     m.add(progress_bar.clone());
     println!("Number of commands: {}", commands.len());
 
-    let mut failures: Vec<String> = Default::default();
-
     // Run commands
-    while let Some((mut cmd, _)) = commands.pop() {
-        let success = run_command(&mut cmd);
+    for bench in commands {
+        let started = manifest::now();
+        let log_dir = Path::new(LOG_DIR)
+            .join(&journal.id)
+            .join(format!("iteration-{}", index + 1));
+        let (success, failures) = run_benchmark(&bench, &settings, &log_dir);
         progress_bar.inc(1);
+
+        let progress = &mut journal.iterations[index];
+        progress.timings.push(BenchmarkTiming {
+            name: bench.name.clone(),
+            started,
+            finished: manifest::now(),
+            success,
+        });
+        progress.failures.extend(failures);
+        progress.completed.push(bench.name.clone());
         if !success {
-            failures.push(format!("{cmd:?}"));
+            progress.failed.push(bench.name);
         }
+        journal.store();
     }
 
     // Check if all commands were succesful
-    if failures.len() > 0 {
+    let progress = &journal.iterations[index];
+    if progress.failed.len() > 0 {
         println!("The following benchmarks failed:");
-        println!("{}", failures.join("\n"));
+        for name in &progress.failed {
+            // The last attempt tells why
+            match progress.failures.iter().rev().find(|failure| &failure.name == name) {
+                Some(failure) => println!("{}: {} ({})", name, failure.kind, failure.message),
+                None => println!("{}", name),
+            }
+        }
     }
-    let target_projects = read_target_projects();
-
     // Save all data
     let timestamp = chrono::offset::Local::now().timestamp_millis().to_string();
-    for record in &target_projects {
-        let project = Project::load(&record.name).expect("Could not load project");
-        move_data_for_project(project, &timestamp);
+    for project in &projects {
+        move_data_for_project(project.clone(), &timestamp);
+    }
+    let progress = &journal.iterations[index];
+    Manifest {
+        run: timestamp.clone(),
+        journal: Some(journal.id.clone()),
+        iteration: Some(index),
+        rmit: true,
+        settings: journal.settings.clone(),
+        environments: progress.environments.clone(),
+        projects: projects
+            .iter()
+            .map(|project| ProjectRevision::capture(&project.name))
+            .collect(),
+        order: progress.order.iter().map(|command| command.name.clone()).collect(),
+        benchmarks: progress.timings.clone(),
+        failures: progress.failures.clone(),
     }
+    .write(&run_dir(&timestamp));
+    journal.iterations[index].saved = Some(timestamp.clone());
+    journal.store();
+    store_run(&timestamp);
 }
 
 #[test]
@@ -382,16 +644,28 @@ fn test_move() {
     let timestamp = chrono::offset::Local::now()
         .format("%Y%m%d%H%M%S")
         .to_string();
-    for record in read_target_projects() {
-        let project = Project::load(&record.name).expect("Could not load project");
+    for project in Registry::open_default().targets() {
         move_data_for_project(project, &timestamp);
     }
 }
 
+fn run_dir(timestamp: &str) -> PathBuf {
+    env::current_dir().unwrap().join("data").join(timestamp)
+}
+
+fn store_run(timestamp: &str) {
+    let run = run_dir(timestamp);
+    let mut store = Store::open_default();
+    store.insert_run(&run);
+    println!(
+        "Stored {} samples of run {} in {}",
+        store.run_samples(timestamp).unwrap_or(0),
+        timestamp,
+        store::DEFAULT_PATH
+    );
+}
+
 fn move_data_for_project(project: Project, timestamp: &str) {
-    let from = get_workdir_for_project(&project.name)
-        .join("target")
-        .join("criterion");
     let to = env::current_dir()
         .unwrap()
         .join("data")
@@ -403,31 +677,69 @@ fn move_data_for_project(project: Project, timestamp: &str) {
         .output()
         .unwrap();
 
-    Command::new("mv")
-        .args([
-            &from.to_string_lossy().to_str(),
-            &to.to_string_lossy().to_string(),
-        ])
-        .status()
-        .unwrap();
+    // Every harness keeps its results in its own directory
+    for harness in HarnessKind::ALL {
+        let from = get_workdir_for_project(&project.name)
+            .join("target")
+            .join(harness.adapter().results_dir());
+        if !from.is_dir() {
+            continue;
+        }
+
+        Command::new("mv")
+            .args([
+                &from.to_string_lossy().to_str(),
+                &to.to_string_lossy().to_string(),
+            ])
+            .status()
+            .unwrap();
+    }
 }
 
-fn run_command(command: &mut Command) -> bool {
-    debugln!("{command:?}");
-    debugln!("workdir: {:?}", command.get_current_dir());
-    let output = command.output().unwrap();
-    debugln!("{}", String::from_utf8(output.stdout).unwrap());
-    output.status.success()
+/// Run one benchmark, retrying it according to the settings, and keep its stdout when that is
+/// where the harness reports the results. Returns whether it succeeded and every failed attempt.
+fn run_benchmark(bench: &BenchCommand, settings: &RunSettings, log_dir: &Path) -> (bool, Vec<Failure>) {
+    let adapter = bench.harness.adapter();
+    let timeout = settings.timeout.map(Duration::from_secs);
+    let mut names = bench.name.split('/');
+    let bench_settings = settings.bench_settings(names.next().unwrap(), names.next().unwrap());
+    let mut failures = vec![];
+    let mut attempt = 1;
+    loop {
+        let mut command = bench_command(bench, &bench_settings);
+        debugln!("{command:?}");
+        debugln!("workdir: {:?}", command.get_current_dir());
+        let execution = execute(&mut command, timeout, log_dir, &bench.name, attempt);
+        debugln!("{}", execution.stdout);
+
+        let failure = execution.failure(bench.harness);
+        let fatal = failure.as_ref().map_or(false, |failure| failure.kind.is_fatal());
+        if !fatal && adapter.keeps_stdout() {
+            // Named after the bench file, so results can be mapped back to it
+            let bench_file = bench.name.split('/').nth(1).unwrap();
+            let dir = bench.workdir.join("target").join(adapter.results_dir());
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join(format!("{}.txt", bench_file));
+            let mut file = OpenOptions::new().create(true).append(true).open(&path).unwrap();
+            file.write_all(execution.stdout.as_bytes())
+                .unwrap_or_else(|err| panic!("Could not write results to {:?}: {}", path, err));
+        }
+
+        let failure = match failure {
+            Some(failure) => failure,
+            None => return (true, failures),
+        };
+        println!("{} failed with {}: {}", bench.name, failure.kind, failure.message);
+        let retry = fatal && attempt <= settings.retries && settings.retry_on.contains(&failure.kind);
+        failures.push(failure);
+        if !retry {
+            return (!fatal, failures);
+        }
+        attempt += 1;
+    }
 }
 
-fn criterion_bench_command(
-    executable: &str,
-    benchmark_id: &str,
-    workdir: &PathBuf,
-    measurement_time: &u64,
-    warmup_time: &u64,
-    sample_size: &u64,
-) -> Command {
+fn bench_command(bench: &BenchCommand, settings: &BenchSettings) -> Command {
     let mut bench_binary = Command::new("cset");
 
     // Setup `cpuset`
@@ -435,15 +747,16 @@ fn criterion_bench_command(
 
     // Configure the benchmark settings
     bench_binary
-        .current_dir(workdir.as_path())
+        .current_dir(bench.workdir.as_path())
+        .envs(&settings.env)
         // The Benchmark
-        .args(&[executable, "--bench"])
-        .args(["--measurement-time", &measurement_time.to_str()])
-        .args(["--warm-up-time", &warmup_time.to_str()])
-        .args(["--sample-size", &sample_size.to_str()])
-        // Criterion uses a regex to select benchmarks,
-        // so we do this to prevent selecting multiple benchmarks to run
-        .arg(format!("^{}$", benchmark_id));
+        .arg(&bench.executable)
+        .args(bench.harness.adapter().bench_args(
+            &bench.benchmark,
+            settings.measurement_time,
+            settings.warmup_time,
+            settings.sample_size,
+        ));
     bench_binary
 }
 
@@ -486,6 +799,11 @@ pub fn compile_benchmark_file(
         .arg("--no-run")
         .arg("--message-format=json");
 
+    // Members outside of `default-members` are only built when selected
+    if let Some(package) = &benchmark.package {
+        cargo.arg("--package").arg(package);
+    }
+
     if let Some(args) = args {
         cargo.args(args);
     }
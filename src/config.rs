@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cpu::{CpuList, CpuSelection};
+use crate::design::Design;
+use crate::execute::FailureKind;
+use crate::journal::RunSettings;
+
+/// Loaded by `power run --config`, and stored with the resolved settings in every run directory
+pub const CONFIG_PATH: &str = "power.toml";
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// Settings changed for one project, or for one bench file in `bench_files`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Overrides {
+    pub measurement_time: Option<u64>,
+    pub warmup_time: Option<u64>,
+    pub sample_size: Option<u64>,
+    /// Enabled on top of the features the bench file requires
+    pub features: Option<Vec<String>>,
+    /// A rustup toolchain like `nightly`, passed to cargo as `+nightly`
+    pub toolchain: Option<String>,
+    /// Set when compiling and when running the benchmarks
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "is_false")]
+    pub exclude: bool,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub bench_files: BTreeMap<String, Overrides>,
+}
+
+/// The settings one bench file is compiled and run with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchSettings {
+    pub measurement_time: u64,
+    pub warmup_time: u64,
+    pub sample_size: u64,
+    pub features: Vec<String>,
+    pub toolchain: Option<String>,
+    pub env: BTreeMap<String, String>,
+    pub exclude: bool,
+}
+
+impl Overrides {
+    fn apply(&self, settings: &mut BenchSettings) {
+        if let Some(measurement_time) = self.measurement_time {
+            settings.measurement_time = measurement_time;
+        }
+        if let Some(warmup_time) = self.warmup_time {
+            settings.warmup_time = warmup_time;
+        }
+        if let Some(sample_size) = self.sample_size {
+            settings.sample_size = sample_size;
+        }
+        if let Some(features) = &self.features {
+            settings.features = features.clone();
+        }
+        if let Some(toolchain) = &self.toolchain {
+            settings.toolchain = Some(toolchain.clone());
+        }
+        settings.env.extend(self.env.clone());
+        settings.exclude |= self.exclude;
+    }
+}
+
+impl BenchSettings {
+    /// The global settings with the overrides of the project and then of the bench file.
+    pub fn resolve(settings: &RunSettings, project: &str, bench_file: &str) -> BenchSettings {
+        let mut resolved = BenchSettings {
+            measurement_time: settings.measurement_time,
+            warmup_time: settings.warmup_time,
+            sample_size: settings.sample_size,
+            features: vec![],
+            toolchain: None,
+            env: BTreeMap::new(),
+            exclude: false,
+        };
+        if let Some(project) = settings.overrides.get(project) {
+            project.apply(&mut resolved);
+            if let Some(bench_file) = project.bench_files.get(bench_file) {
+                bench_file.apply(&mut resolved);
+            }
+        }
+        resolved
+    }
+
+    /// The argument that selects the toolchain for cargo
+    pub fn cargo_toolchain(&self) -> Option<String> {
+        self.toolchain
+            .as_ref()
+            .map(|toolchain| format!("+{}", toolchain.trim_start_matches('+')))
+    }
+}
+
+/// An experiment, every setting that is left out comes from the command line.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub repetitions: Option<usize>,
+    pub measurement_time: Option<u64>,
+    pub warmup_time: Option<u64>,
+    pub sample_size: Option<u64>,
+    /// In the kernel list format, like `--bench-cpus`
+    pub bench_cpus: Option<String>,
+    pub housekeeping_cpus: Option<String>,
+    pub timeout: Option<u64>,
+    pub retries: Option<usize>,
+    pub retry_on: Option<Vec<FailureKind>>,
+    pub jobs: Option<usize>,
+    pub design: Option<Design>,
+    pub seed: Option<u64>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub projects: BTreeMap<String, Overrides>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Config {
+        let content = fs::read_to_string(path)
+            .unwrap_or_else(|err| panic!("Could not read config {:?}: {}", path, err));
+        toml::from_str(&content)
+            .unwrap_or_else(|err| panic!("Could not parse config {:?}: {}", path, err))
+    }
+
+    /// Apply the config to `settings`, except for the settings given on the command line.
+    pub fn apply(self, settings: &mut RunSettings, on_command_line: impl Fn(&str) -> bool) {
+        let cpus = |cpus: String| {
+            cpus.parse::<CpuList>()
+                .unwrap_or_else(|err| panic!("Invalid CPUs in config: {}", err))
+        };
+        macro_rules! apply {
+            ($argument:literal, $value:expr, $field:expr) => {
+                if let Some(value) = $value {
+                    if !on_command_line($argument) {
+                        $field = value;
+                    }
+                }
+            };
+        }
+
+        apply!("repetitions", self.repetitions, settings.iterations);
+        apply!(
+            "measurement_time",
+            self.measurement_time,
+            settings.measurement_time
+        );
+        apply!("warmup_time", self.warmup_time, settings.warmup_time);
+        apply!("sample_size", self.sample_size, settings.sample_size);
+        apply!("bench_cpus", self.bench_cpus.map(cpus), settings.cpus.bench);
+        apply!(
+            "housekeeping_cpus",
+            self.housekeeping_cpus.map(cpus),
+            settings.cpus.housekeeping
+        );
+        apply!("timeout", self.timeout.map(Some), settings.timeout);
+        apply!("retries", self.retries, settings.retries);
+        let retryable = |kinds: Vec<FailureKind>| {
+            for kind in &kinds {
+                if !kind.is_retryable() {
+                    panic!("Invalid retry_on in config: `{}` can not be retried", kind);
+                }
+            }
+            kinds
+        };
+        apply!("retry_on", self.retry_on.map(retryable), settings.retry_on);
+        apply!("jobs", self.jobs.map(Some), settings.jobs);
+        apply!("design", self.design, settings.design);
+        apply!("seed", self.seed, settings.seed);
+        settings.overrides = self.projects;
+    }
+
+    /// The config a run was started with, with every setting filled in.
+    pub fn resolved(settings: &RunSettings) -> Config {
+        let CpuSelection {
+            bench,
+            housekeeping,
+        } = &settings.cpus;
+        Config {
+            repetitions: Some(settings.iterations),
+            measurement_time: Some(settings.measurement_time),
+            warmup_time: Some(settings.warmup_time),
+            sample_size: Some(settings.sample_size),
+            bench_cpus: Some(bench.to_string()),
+            housekeeping_cpus: Some(housekeeping.to_string()),
+            timeout: settings.timeout,
+            retries: Some(settings.retries),
+            retry_on: Some(settings.retry_on.clone()),
+            jobs: Some(settings.compile_jobs()),
+            design: Some(settings.design),
+            seed: Some(settings.seed),
+            projects: settings.overrides.clone(),
+        }
+    }
+
+    pub fn write(&self, path: &Path) {
+        fs::write(path, toml::to_string(self).unwrap())
+            .unwrap_or_else(|err| panic!("Could not write config {:?}: {}", path, err));
+    }
+}
+
+#[test]
+fn test_config() {
+    let config: Config = toml::from_str(
+        r#"
+repetitions = 10
+measurement_time = 20
+bench_cpus = "2-3"
+design = "latin-square"
+
+[projects.chrono]
+sample_size = 100
+features = ["unstable-locales"]
+env = { RUSTFLAGS = "-C target-cpu=native" }
+
+[projects.chrono.bench_files.serde]
+exclude = true
+
+[projects.chrono.bench_files.chrono]
+measurement_time = 60
+toolchain = "nightly"
+env = { RAYON_NUM_THREADS = "1" }
+"#,
+    )
+    .unwrap();
+
+    let mut settings = RunSettings {
+        iterations: 30,
+        measurement_time: 30,
+        warmup_time: 5,
+        sample_size: 300,
+        cpus: CpuSelection {
+            bench: CpuList(vec![3]),
+            housekeeping: CpuList(vec![0]),
+        },
+        timeout: None,
+        retries: 0,
+        retry_on: vec![],
+        jobs: Some(4),
+        design: Design::Rmit,
+        seed: 1,
+        overrides: BTreeMap::new(),
+    };
+    // `-r 30` was given on the command line
+    config.apply(&mut settings, |argument| argument == "repetitions");
+    assert_eq!(settings.iterations, 30);
+    assert_eq!(settings.measurement_time, 20);
+    assert_eq!(settings.cpus.bench, CpuList(vec![2, 3]));
+    assert_eq!(settings.design, Design::LatinSquare);
+
+    let chrono = settings.bench_settings("chrono", "chrono");
+    assert_eq!(chrono.measurement_time, 60);
+    assert_eq!(chrono.sample_size, 100);
+    assert_eq!(chrono.features, vec!["unstable-locales".to_string()]);
+    assert_eq!(chrono.cargo_toolchain(), Some("+nightly".to_string()));
+    assert_eq!(chrono.env.len(), 2);
+    assert!(settings.bench_settings("chrono", "serde").exclude);
+    assert_eq!(
+        settings.bench_settings("regex", "regex").measurement_time,
+        20
+    );
+
+    // The stored config loads into the same settings
+    let resolved: Config =
+        toml::from_str(&toml::to_string(&Config::resolved(&settings)).unwrap()).unwrap();
+    assert_eq!(resolved.projects, settings.overrides);
+    assert_eq!(resolved.bench_cpus, Some("2,3".to_string()));
+}
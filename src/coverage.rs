@@ -9,8 +9,10 @@ use std::rc::Rc;
 use regex::Regex;
 use crate::collect::compile_benchmark_file;
 use crate::data::llvmcovdata::{Filter, LlvmCovData};
-use crate::data::project::{BenchFile, Project, read_target_projects};
+use crate::data::project::BenchFile;
+use crate::data::registry::Registry;
 use crate::data::syn_visit::visit_function_syn;
+use crate::store::Store;
 
 fn compile_for_coverage(benchmark_file: &BenchFile) -> Option<String> {
     let exe = compile_benchmark_file(
@@ -108,14 +110,13 @@ fn compile_for_callgrind(benchmark_file: &BenchFile) -> Option<String> {
 pub fn gather_instructions() {
     let re = Regex::new(r"==\d+==\sCollected : (\d+)").unwrap();
     let mut file = OpenOptions::new().write(true).truncate(true).create(true).open("instructions.csv").unwrap();
-    for record in read_target_projects() {
-        let project = Project::load(&record.name).expect("Could not load project");
+    let mut store = Store::open_default();
+    for project in Registry::open_default().targets() {
         for benchmark_file in project.bench_files {
             let _ = compile_for_callgrind(&benchmark_file).unwrap();
         }
     }
-    for record in read_target_projects() {
-        let project = Project::load(&record.name).expect("Could not load project");
+    for project in Registry::open_default().targets() {
         for benchmark_file in project.bench_files {
             let coverage_executable = match compile_for_callgrind(&benchmark_file) {
                 None => {println!("failed to compile"); panic!(); break;}
@@ -141,14 +142,15 @@ pub fn gather_instructions() {
                 file.write(b"; ").unwrap();
                 file.write(instr.as_bytes()).unwrap();
                 file.write(b"\n").unwrap();
+                store.insert_instructions(&project.name, &benchmark_file.name, id, instr.parse().unwrap());
             }
         }
     }
 }
 
     pub fn gather_coverage() {
-        for record in read_target_projects() {
-            let project = Project::load(&record.name).expect("Could not load project");
+        let mut store = Store::open_default();
+        for project in Registry::open_default().targets() {
             for benchmark_file in project.bench_files {
                 let coverage_executable = compile_for_coverage(&benchmark_file);
                 let coverage_executable = if coverage_executable.is_some() {
@@ -186,6 +188,8 @@ pub fn gather_instructions() {
 
                     let language_features_path = profraw_path.replace("profraw", "csv");
                     save_language_features(&visit_counter, language_features_path);
+                    let recorded = PathBuf::from(&profraw_path).file_stem().unwrap().to_string_lossy().to_string();
+                    store.insert_language_features(&project.name, &benchmark_file.name, id, &recorded, &visit_counter);
                 }
             }
         }
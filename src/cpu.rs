@@ -0,0 +1,207 @@
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+pub const SYSFS_CPU: &str = "/sys/devices/system/cpu";
+
+/// CPUs in the kernel list format, e.g. `0-3,8`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CpuList(pub Vec<usize>);
+
+impl FromStr for CpuList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut cpus = BTreeSet::new();
+        for part in s.trim().split(',').filter(|part| !part.is_empty()) {
+            let parse = |cpu: &str| {
+                cpu.trim()
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid CPU `{}` in `{}`", cpu, s))
+            };
+            match part.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (parse(first)?, parse(last)?);
+                    if first > last {
+                        return Err(format!("Reversed range `{}` in `{}`", part, s));
+                    }
+                    cpus.extend(first..=last);
+                }
+                None => {
+                    cpus.insert(parse(part)?);
+                }
+            }
+        }
+        Ok(CpuList(cpus.into_iter().collect()))
+    }
+}
+
+impl Display for CpuList {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let cpus = self.0.iter().map(usize::to_string).collect::<Vec<String>>();
+        write!(f, "{}", cpus.join(","))
+    }
+}
+
+/// The CPUs that stay online while benchmarking: benchmarks run on `bench`,
+/// everything else that cannot be avoided runs on `housekeeping`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CpuSelection {
+    pub bench: CpuList,
+    pub housekeeping: CpuList,
+}
+
+fn read_cpu_list(path: &Path) -> Result<CpuList, String> {
+    fs::read_to_string(path)
+        .map_err(|err| format!("Could not read {:?}: {}", path, err))?
+        .parse()
+}
+
+/// The CPUs sharing a physical core with `cpu`, including itself.
+/// Only available while `cpu` is online.
+pub fn thread_siblings(root: &Path, cpu: usize) -> Result<CpuList, String> {
+    read_cpu_list(
+        &root
+            .join(format!("cpu{}", cpu))
+            .join("topology")
+            .join("thread_siblings_list"),
+    )
+}
+
+/// CPUs without an `online` file (usually cpu0) can not be taken offline.
+pub fn hotpluggable(root: &Path, cpu: usize) -> bool {
+    root.join(format!("cpu{}", cpu)).join("online").exists()
+}
+
+impl CpuSelection {
+    /// Every CPU that should be online.
+    pub fn keep(&self) -> Vec<usize> {
+        self.bench
+            .0
+            .iter()
+            .chain(self.housekeeping.0.iter())
+            .cloned()
+            .collect::<BTreeSet<usize>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Checks the selection against the topology, must be called while the benchmark CPUs are online.
+    pub fn validate(&self, root: &Path) -> Result<(), String> {
+        if self.bench.0.is_empty() {
+            return Err("At least one benchmark CPU is needed".to_string());
+        }
+        let present = read_cpu_list(&root.join("present"))?;
+        for cpu in self.keep() {
+            if !present.0.contains(&cpu) {
+                return Err(format!(
+                    "CPU {} is not present, present are {}",
+                    cpu, present
+                ));
+            }
+        }
+        if let Some(cpu) = self
+            .bench
+            .0
+            .iter()
+            .find(|cpu| self.housekeeping.0.contains(cpu))
+        {
+            return Err(format!(
+                "CPU {} is selected for both benchmarks and housekeeping",
+                cpu
+            ));
+        }
+
+        for &cpu in &self.bench.0 {
+            let siblings = thread_siblings(root, cpu)?;
+            // Housekeeping on the other thread of a benchmark core defeats the isolation
+            if let Some(sibling) = siblings.0.iter().find(|s| self.housekeeping.0.contains(s)) {
+                return Err(format!(
+                    "Housekeeping CPU {} is a SMT sibling of benchmark CPU {}",
+                    sibling, cpu
+                ));
+            }
+            if let Some(sibling) = siblings
+                .0
+                .iter()
+                .find(|s| **s != cpu && self.bench.0.contains(s))
+            {
+                println!(
+                    "Warning: benchmark CPUs {} and {} share a physical core",
+                    cpu, sibling
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that exactly the selected CPUs are online, and the CPUs that can not be taken offline.
+    pub fn verify(&self, root: &Path) -> Result<(), String> {
+        let present = read_cpu_list(&root.join("present"))?;
+        let expected = present
+            .0
+            .into_iter()
+            .filter(|cpu| !hotpluggable(root, *cpu))
+            .chain(self.keep())
+            .collect::<BTreeSet<usize>>()
+            .into_iter()
+            .collect::<Vec<usize>>();
+        let online = read_cpu_list(&root.join("online"))?;
+        if online.0 != expected {
+            return Err(format!(
+                "Expected CPUs {} to be online, but {} are",
+                CpuList(expected),
+                online
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_cpu_selection() {
+    assert_eq!("0-2,5".parse::<CpuList>(), Ok(CpuList(vec![0, 1, 2, 5])));
+    assert_eq!("3\n".parse::<CpuList>(), Ok(CpuList(vec![3])));
+    assert!("a".parse::<CpuList>().is_err());
+    assert!("3-1".parse::<CpuList>().is_err());
+
+    // 4 cores with 2 threads each, cpu N and N + 4 are siblings
+    let root = tempfile::tempdir().unwrap();
+    fs::write(root.path().join("present"), "0-7\n").unwrap();
+    fs::write(root.path().join("online"), "0,3\n").unwrap();
+    for cpu in 0..8 {
+        let topology = root.path().join(format!("cpu{}", cpu)).join("topology");
+        fs::create_dir_all(&topology).unwrap();
+        if cpu != 0 {
+            fs::write(
+                root.path().join(format!("cpu{}", cpu)).join("online"),
+                "1\n",
+            )
+            .unwrap();
+        }
+        fs::write(
+            topology.join("thread_siblings_list"),
+            format!("{},{}\n", cpu % 4, cpu % 4 + 4),
+        )
+        .unwrap();
+    }
+
+    let selection = |bench: &str, housekeeping: &str| CpuSelection {
+        bench: bench.parse().unwrap(),
+        housekeeping: housekeeping.parse().unwrap(),
+    };
+    assert!(selection("3", "0").validate(root.path()).is_ok());
+    assert!(selection("3", "0").verify(root.path()).is_ok());
+    assert!(selection("3", "7").validate(root.path()).is_err());
+    assert!(selection("3", "3").validate(root.path()).is_err());
+    assert!(selection("8", "0").validate(root.path()).is_err());
+    assert!(selection("2-3", "0").verify(root.path()).is_err());
+
+    // cpu0 stays online even when it is not selected
+    fs::write(root.path().join("online"), "0,1,3\n").unwrap();
+    assert!(selection("3", "1").verify(root.path()).is_ok());
+}
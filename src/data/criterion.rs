@@ -0,0 +1,49 @@
+// Models for the files Criterion writes to `target/criterion/<benchmark>/new/`.
+// Field names follow Criterion's own (de)serialization:
+// https://github.com/bheisler/criterion.rs/blob/master/src/report.rs
+// https://github.com/bheisler/criterion.rs/blob/master/src/estimate.rs
+use serde::{Deserialize, Serialize};
+
+/// `benchmark.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkId {
+    pub(crate) group_id: String,
+    pub(crate) function_id: Option<String>,
+    pub(crate) value_str: Option<String>,
+    pub(crate) throughput: Option<serde_json::Value>,
+    pub(crate) full_id: String,
+    pub(crate) directory_name: String,
+    pub(crate) title: String,
+}
+
+/// `sample.json`, `times` are the total measured nanoseconds for `iters` iterations.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SampleData {
+    pub(crate) sampling_mode: String,
+    pub(crate) iters: Vec<f64>,
+    pub(crate) times: Vec<f64>,
+}
+
+/// `estimates.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Estimates {
+    pub(crate) mean: Estimate,
+    pub(crate) median: Estimate,
+    pub(crate) median_abs_dev: Estimate,
+    pub(crate) slope: Option<Estimate>,
+    pub(crate) std_dev: Estimate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Estimate {
+    pub(crate) confidence_interval: ConfidenceInterval,
+    pub(crate) point_estimate: f64,
+    pub(crate) standard_error: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfidenceInterval {
+    pub(crate) confidence_level: f64,
+    pub(crate) lower_bound: f64,
+    pub(crate) upper_bound: f64,
+}
@@ -0,0 +1,178 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Deserialize;
+
+/// The output of `cargo metadata --no-deps --format-version 1`, as far as it is used.
+#[derive(Debug, Deserialize)]
+pub struct Metadata {
+    pub packages: Vec<Package>,
+    pub workspace_members: Vec<String>,
+    pub workspace_root: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Package {
+    pub name: String,
+    pub id: String,
+    pub manifest_path: PathBuf,
+    pub targets: Vec<Target>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Target {
+    pub name: String,
+    pub kind: Vec<String>,
+    pub src_path: PathBuf,
+    #[serde(rename = "required-features", default)]
+    pub required_features: Vec<String>,
+}
+
+/// Why `cargo metadata` gave no usable output.
+#[derive(Debug)]
+pub struct MetadataError {
+    /// Exit code of `cargo metadata`, `None` when it did not run or was killed
+    pub status: Option<i32>,
+    pub message: String,
+}
+
+impl Display for MetadataError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Metadata {
+    /// Cargo's resolved view of the workspace in `dir`, with auto-discovered targets
+    /// and without excluded members.
+    pub fn read(dir: &Path) -> Result<Metadata, MetadataError> {
+        let output = Command::new("cargo")
+            .current_dir(dir)
+            .args(["metadata", "--no-deps", "--format-version", "1"])
+            .output()
+            .map_err(|err| MetadataError {
+                status: None,
+                message: format!("could not run cargo metadata in {:?}: {}", dir, err),
+            })?;
+        if !output.status.success() {
+            return Err(MetadataError {
+                status: output.status.code(),
+                message: String::from_utf8_lossy(&output.stderr).to_string(),
+            });
+        }
+        serde_json::from_slice(&output.stdout).map_err(|err| MetadataError {
+            status: output.status.code(),
+            message: format!("could not parse cargo metadata of {:?}: {}", dir, err),
+        })
+    }
+
+    /// The packages of the workspace, path dependencies outside of it are left out.
+    pub fn members(&self) -> impl Iterator<Item = &Package> {
+        self.packages
+            .iter()
+            .filter(|package| self.workspace_members.contains(&package.id))
+    }
+}
+
+impl Package {
+    pub fn bench_targets(&self) -> impl Iterator<Item = &Target> {
+        self.targets
+            .iter()
+            .filter(|target| target.kind.iter().any(|kind| kind == "bench"))
+    }
+
+    /// The directory with the `Cargo.toml` of the package
+    pub fn dir(&self) -> &Path {
+        self.manifest_path.parent().unwrap()
+    }
+}
+
+#[test]
+fn test_bench_targets() {
+    let metadata: Metadata = serde_json::from_str(
+        r#"{
+  "packages": [
+    {
+      "name": "chrono",
+      "version": "0.4.24",
+      "id": "chrono 0.4.24 (path+file:///projects/chrono)",
+      "manifest_path": "/projects/chrono/Cargo.toml",
+      "targets": [
+        {"kind": ["lib"], "crate_types": ["lib"], "name": "chrono", "src_path": "/projects/chrono/src/lib.rs", "edition": "2018", "doc": true, "doctest": true, "test": true},
+        {"kind": ["bench"], "crate_types": ["bin"], "name": "chrono", "src_path": "/projects/chrono/benches/chrono.rs", "edition": "2018", "required-features": ["__internal_bench"], "doc": false, "doctest": false, "test": false},
+        {"kind": ["bench"], "crate_types": ["bin"], "name": "serde", "src_path": "/projects/chrono/benches/serde/main.rs", "edition": "2018", "doc": false, "doctest": false, "test": false}
+      ]
+    },
+    {
+      "name": "chrono-tz",
+      "version": "0.8.2",
+      "id": "chrono-tz 0.8.2 (path+file:///projects/chrono/tz)",
+      "manifest_path": "/projects/chrono/tz/Cargo.toml",
+      "targets": [
+        {"kind": ["bench"], "crate_types": ["bin"], "name": "tz", "src_path": "/projects/chrono/tz/benches/tz.rs", "edition": "2018", "doc": false, "doctest": false, "test": false}
+      ]
+    }
+  ],
+  "workspace_members": ["chrono 0.4.24 (path+file:///projects/chrono)"],
+  "workspace_root": "/projects/chrono",
+  "metadata": null
+}"#,
+    )
+    .unwrap();
+
+    let members = metadata.members().collect::<Vec<_>>();
+    assert_eq!(members.len(), 1);
+    assert_eq!(members[0].dir(), Path::new("/projects/chrono"));
+    let benches = members[0].bench_targets().collect::<Vec<_>>();
+    assert_eq!(benches.len(), 2);
+    assert_eq!(benches[0].required_features, vec!["__internal_bench"]);
+    assert_eq!(
+        benches[1].src_path,
+        Path::new("/projects/chrono/benches/serde/main.rs")
+    );
+}
+
+#[test]
+fn test_workspace_members() {
+    let dir = tempfile::tempdir().unwrap();
+    let write = |path: &str, content: &str| {
+        let path = dir.path().join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    };
+    let package = |name: &str| {
+        format!(
+            "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+            name
+        )
+    };
+    write(
+        "Cargo.toml",
+        "[workspace]\nmembers = [\"crates/*\"]\nexclude = [\"crates/skipped\"]\ndefault-members = [\"crates/core\"]\n",
+    );
+    for name in ["core", "extra", "skipped"] {
+        write(&format!("crates/{}/Cargo.toml", name), &package(name));
+        write(&format!("crates/{}/src/lib.rs", name), "");
+    }
+    write("crates/extra/benches/parse/main.rs", "fn main() {}");
+
+    let metadata = Metadata::read(dir.path()).unwrap();
+    let mut members = metadata
+        .members()
+        .map(|package| package.name.as_str())
+        .collect::<Vec<_>>();
+    members.sort();
+    assert_eq!(members, vec!["core", "extra"]);
+    let extra = metadata
+        .members()
+        .find(|package| package.name == "extra")
+        .unwrap();
+    let benches = extra.bench_targets().collect::<Vec<_>>();
+    assert_eq!(benches.len(), 1);
+    assert_eq!(benches[0].name, "parse");
+
+    write("Cargo.toml", "[workspace");
+    let err = Metadata::read(dir.path()).unwrap_err();
+    assert_eq!(err.status, Some(101));
+}
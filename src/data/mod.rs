@@ -3,8 +3,11 @@ use std::hash::Hash;
 use std::ops::AddAssign;
 
 pub(crate) mod compileroutput;
+pub(crate) mod criterion;
 pub(crate) mod llvmcovdata;
+pub(crate) mod metadata;
 pub(crate) mod project;
+pub(crate) mod registry;
 pub(crate) mod syn_visit;
 
 trait Update<K, V>
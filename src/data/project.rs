@@ -1,29 +1,106 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::path::{Path, PathBuf};
 use std::process::{Command, ExitStatus};
 
-use cargo_toml::{Manifest, Product};
-use lazy_static::lazy_static;
-use regex::Regex;
+use cargo_toml::Manifest;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::data::metadata::Metadata;
+use crate::harness::HarnessKind;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Project {
     pub name: String,
     pub bench_files: Vec<BenchFile>,
+    /// Bench targets that were left out because their benchmarks could not be listed
+    #[serde(default)]
+    pub listing_failures: Vec<ListingFailure>,
 }
 
-impl Project {
-    pub(crate) fn store(&self) -> std::io::Result<()> {
-        let serialized = serde_json::to_string(self).unwrap();
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ListingFailureKind {
+    /// `cargo metadata` failed, so the bench targets of the project are unknown
+    Metadata,
+    /// Another workspace member has a bench target with the same name
+    DuplicateName,
+    /// The source matches none of the supported harnesses
+    UnknownHarness,
+    /// The `Cargo.toml` of the package could not be parsed, so `harness = false` is unknown
+    InvalidManifest,
+    CompileError,
+    /// Compiled, but listing exited with an error
+    Error,
+    /// Listed, but no line looked like a benchmark
+    UnexpectedOutput,
+    /// Listed nothing at all
+    NoBenchmarks,
+}
 
-        std::fs::write(format!("{}.json", self.name), serialized)
+impl Display for ListingFailureKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListingFailureKind::Metadata => write!(f, "cargo metadata failed"),
+            ListingFailureKind::DuplicateName => write!(f, "duplicate name"),
+            ListingFailureKind::UnknownHarness => write!(f, "unknown harness"),
+            ListingFailureKind::InvalidManifest => write!(f, "invalid manifest"),
+            ListingFailureKind::CompileError => write!(f, "compile error"),
+            ListingFailureKind::Error => write!(f, "error"),
+            ListingFailureKind::UnexpectedOutput => write!(f, "unexpected output"),
+            ListingFailureKind::NoBenchmarks => write!(f, "no benchmarks"),
+        }
     }
+}
+
+/// A bench target whose benchmarks could not be listed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ListingFailure {
+    /// Empty when no bench target of the project could be found
+    pub bench_file: String,
+    pub package: Option<String>,
+    pub kind: ListingFailureKind,
+    /// Exit code of `cargo bench -- --list`, `None` when it did not run or was killed
+    pub status: Option<i32>,
+    /// The first error and what follows it, or the end of the output
+    pub excerpt: String,
+}
+
+/// Lines of output kept in a `ListingFailure`
+const EXCERPT_LINES: usize = 20;
 
-    pub fn load(project: &str) -> serde_json::Result<Project> {
-        serde_json::from_str::<Project>(
-            &std::fs::read_to_string(format!("{}.json", project))
-                .expect(&format!("Could not read project {}.json", project)),
-        )
+fn excerpt(output: &str) -> String {
+    let lines = output.lines().collect::<Vec<&str>>();
+    let start = lines
+        .iter()
+        .position(|line| line.starts_with("error"))
+        .unwrap_or(lines.len().saturating_sub(EXCERPT_LINES));
+    lines[start..(start + EXCERPT_LINES).min(lines.len())].join("\n")
+}
+
+/// Why listing found no benchmarks, `None` if it found some.
+fn classify_listing(
+    success: bool,
+    stdout: &str,
+    stderr: &str,
+    benchmarks: usize,
+) -> Option<ListingFailureKind> {
+    if !success {
+        if stderr.contains("could not compile") || stderr.contains("error[E") {
+            Some(ListingFailureKind::CompileError)
+        } else {
+            Some(ListingFailureKind::Error)
+        }
+    } else if benchmarks > 0 {
+        None
+    } else if stdout
+        .lines()
+        .all(|line| line.trim().is_empty() || line.ends_with(" 0 benchmarks"))
+    {
+        // libtest ends its list with `0 tests, 0 benchmarks`
+        Some(ListingFailureKind::NoBenchmarks)
+    } else {
+        Some(ListingFailureKind::UnexpectedOutput)
     }
 }
 
@@ -41,9 +118,24 @@ pub struct BenchFile {
     pub source: String,
     pub features: Vec<String>,
     pub benches: Vec<String>,
+    #[serde(default)]
+    pub harness: HarnessKind,
+    /// The workspace member the bench target belongs to, `None` in projects parsed before members were recorded
+    #[serde(default)]
+    pub package: Option<String>,
 }
 
 impl BenchFile {
+    /// What runs one at a time, a bench file whose harness can not select benchmarks
+    /// runs as a whole under its own name.
+    pub fn runs(&self) -> Vec<String> {
+        if self.harness.adapter().selects_benchmarks() {
+            self.benches.clone()
+        } else {
+            vec![self.name.clone()]
+        }
+    }
+
     pub fn get_workdir(&self) -> String {
         let mut buf = Path::new(&self.source).to_path_buf();
         buf.pop();
@@ -51,138 +143,185 @@ impl BenchFile {
     }
 }
 
-fn get_manifest(path: &PathBuf) -> Manifest {
-    Manifest::from_path(path.join("Cargo.toml").as_path()).expect(
-        format!(
-            "Could not find Cargo.toml for {}",
-            path.as_path().to_str().to_owned().unwrap()
-        )
-        .as_str(),
-    )
+/// The source of a bench target, whose path may leave out `.rs` or point to a directory with `main.rs`.
+fn read_bench_source(path: &Path) -> String {
+    [
+        path.to_path_buf(),
+        path.with_extension("rs"),
+        path.join("main.rs"),
+    ]
+    .iter()
+    .find_map(|candidate| std::fs::read_to_string(candidate).ok())
+    .unwrap_or_default()
 }
 
 pub fn find_benchmarks_for_project(project_name: &str) -> Project {
     let work_dir = get_workdir_for_project(project_name);
     println!(
-        "Reading Cargo.toml for {} in {}",
+        "Reading cargo metadata for {} in {}",
         project_name,
         work_dir.to_str().unwrap()
     );
 
-    let manifest = get_manifest(&work_dir);
-    let mut project_bench_products = manifest.bench;
-
-    project_bench_products.iter_mut().for_each(|product| {
-        if !product.path.is_some() {
-            product.path.replace(
-                Path::new("benches")
-                    .join(product.name.clone().unwrap())
-                    .to_str()
-                    .unwrap()
-                    .to_string(),
-            );
-        };
-    });
+    let metadata = match Metadata::read(&work_dir) {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            println!("Could not read cargo metadata for {}: {}", project_name, err);
+            return Project {
+                name: project_name.to_string(),
+                bench_files: vec![],
+                listing_failures: vec![ListingFailure {
+                    bench_file: String::new(),
+                    package: None,
+                    kind: ListingFailureKind::Metadata,
+                    status: err.status,
+                    excerpt: excerpt(&err.message),
+                }],
+            };
+        }
+    };
+    let work_path = metadata.workspace_root.as_path();
 
-    // If there are multiple workspaces, also use all benches within those
-    if manifest.workspace.is_some() {
-        project_bench_products.extend(
-            manifest
-                .workspace
+    let mut bench_files: Vec<BenchFile> = vec![];
+    let mut listing_failures = vec![];
+    // Benchmarks are named `project/bench file/id`, so a bench file name may only be used once
+    let mut packages_by_bench_file: HashMap<String, String> = HashMap::new();
+    for package in metadata.members() {
+        // Cargo metadata does not report `harness = false`, so that still comes from the manifest
+        let manifest = match Manifest::from_path(&package.manifest_path) {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                println!(
+                    "Could not parse {:?}, skipping its benches: {}",
+                    package.manifest_path, err
+                );
+                listing_failures.extend(package.bench_targets().map(|target| ListingFailure {
+                    bench_file: target.name.clone(),
+                    package: Some(package.name.clone()),
+                    kind: ListingFailureKind::InvalidManifest,
+                    status: None,
+                    excerpt: err.to_string(),
+                }));
+                continue;
+            }
+        };
+        for target in package.bench_targets() {
+            let product_name = target.name.clone();
+            if let Some(other) = packages_by_bench_file.get(&product_name) {
+                println!(
+                    "{:?} of {} has the same name as a bench target of {}, skipping",
+                    product_name, package.name, other
+                );
+                listing_failures.push(ListingFailure {
+                    excerpt: format!("{} also has a bench target named {}", other, product_name),
+                    bench_file: product_name,
+                    package: Some(package.name.clone()),
+                    kind: ListingFailureKind::DuplicateName,
+                    status: None,
+                });
+                continue;
+            }
+            packages_by_bench_file.insert(product_name.clone(), package.name.clone());
+            let product_path = target
+                .src_path
+                .strip_prefix(work_path)
+                .unwrap_or(&target.src_path)
+                .to_str()
                 .unwrap()
-                .members
+                .to_string();
+            print!(
+                "Checking benches for {:?} of {} in file {:?}... \t",
+                &product_name, &package.name, &product_path
+            );
+
+            // Bench targets without a `[[bench]]` section use the libtest harness
+            let libtest_harness = manifest
+                .bench
                 .iter()
-                .flat_map(|member| {
-                    let mut bench_products = get_manifest(&work_dir.join(member)).bench;
-                    bench_products.iter_mut().for_each(|prod| {
-                        // Prepend the workspace name to the path
-                        if prod.path.is_some() {
-                            prod.path.replace(
-                                Path::new(member)
-                                    .join(prod.path.as_ref().unwrap())
-                                    .to_str()
-                                    .unwrap()
-                                    .to_string(),
-                            );
-                        } else {
-                            prod.path.replace(
-                                Path::new(member)
-                                    .join("benches")
-                                    .join(prod.name.as_ref().unwrap())
-                                    .to_str()
-                                    .unwrap()
-                                    .to_string(),
-                            );
-                        }
+                .find(|product| product.name.as_deref() == Some(target.name.as_str()))
+                .map_or(true, |product| product.harness);
+            let source = read_bench_source(&target.src_path);
+            let harness = match HarnessKind::detect(libtest_harness, &source) {
+                Some(harness) => harness,
+                None => {
+                    println!("Unknown benchmark harness in {:?}, skipping", product_path);
+                    listing_failures.push(ListingFailure {
+                        bench_file: product_name,
+                        package: Some(package.name.clone()),
+                        kind: ListingFailureKind::UnknownHarness,
+                        status: None,
+                        excerpt: String::new(),
                     });
-                    bench_products
-                })
-                .collect::<Vec<Product>>(),
-        );
-    }
+                    continue;
+                }
+            };
+            let adapter = harness.adapter();
 
-    lazy_static! {
-        static ref RE_BENCH_NAME: Regex = Regex::new(r"(?m)^(.+): bench$").unwrap();
-    }
+            let list_args = adapter.list_args();
 
-    let work_path = Path::new(&work_dir);
+            let mut command = Command::new("cargo");
 
-    let mut bench_files: Vec<BenchFile> = vec![];
-    for product in project_bench_products {
-        let product_name = product.name.unwrap();
-        print!(
-            "Checking benches for {:?} in file {:?}... \t",
-            &product_name, &product.path
-        );
-
-        let mut command = Command::new("cargo");
-
-        let product_path = product.path.unwrap();
-        let mut abs_path = work_path.join(&product_path);
-        abs_path.pop(); // remove filename from path
-        println!("{:?}", abs_path);
-        command
-            .current_dir(abs_path.to_str().unwrap().to_string())
-            .arg("bench");
-        // .env("CARGO_PROFILE_BENCH_DEBUG", "true") // We need debug info to find probepoints
-        // .env("CARGO_PROFILE_BENCH_LTO", "no"); // Debug info is stripped if LTO is on
-
-        if product.required_features.len() > 0 {
             command
-                .arg("--features")
-                .arg(product.required_features.join(","));
-        }
+                .current_dir(package.dir())
+                .arg("bench")
+                .arg("--package")
+                .arg(&package.name);
+            // .env("CARGO_PROFILE_BENCH_DEBUG", "true") // We need debug info to find probepoints
+            // .env("CARGO_PROFILE_BENCH_LTO", "no"); // Debug info is stripped if LTO is on
+
+            if target.required_features.len() > 0 {
+                command
+                    .arg("--features")
+                    .arg(target.required_features.join(","));
+            }
 
-        command
-            .arg("--bench")
-            .arg(&product_name)
-            .arg("--")
-            .arg("--list");
-        println!("{:?}", command);
-        let benches = command.output().expect("could not run --bench").stdout;
-
-        let parsed_output = std::str::from_utf8(&*benches).expect("Could not parse UTF-8");
-
-        let benchmark_ids = RE_BENCH_NAME
-            .captures_iter(parsed_output)
-            .map(|c| String::from(&c[1]))
-            .collect::<Vec<String>>();
-
-        let bf = BenchFile {
-            project: project_name.to_string(),
-            name: product_name.to_string(),
-            source: product_path.clone(),
-            features: product.required_features.clone(),
-            benches: benchmark_ids,
-        };
+            command
+                .arg("--bench")
+                .arg(&product_name)
+                .arg("--")
+                .args(list_args);
+            println!("{:?}", command);
+            let output = command.output().expect("could not run --bench");
+            let parsed_output = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+
+            let benchmark_ids = adapter.parse_list(&parsed_output);
+            if let Some(kind) = classify_listing(
+                output.status.success(),
+                &parsed_output,
+                &stderr,
+                benchmark_ids.len(),
+            ) {
+                println!("could not list the benchmarks of {}: {}", product_name, kind);
+                listing_failures.push(ListingFailure {
+                    bench_file: product_name,
+                    package: Some(package.name.clone()),
+                    kind,
+                    status: output.status.code(),
+                    excerpt: excerpt(if output.status.success() {
+                        &parsed_output
+                    } else {
+                        &stderr
+                    }),
+                });
+                continue;
+            }
+
+            let bf = BenchFile {
+                project: project_name.to_string(),
+                name: product_name.to_string(),
+                source: product_path.clone(),
+                features: target.required_features.clone(),
+                benches: benchmark_ids,
+                harness,
+                package: Some(package.name.clone()),
+            };
 
-        println!(
-            "found {} benchmark(s) for {}",
-            bf.benches.len(),
-            product_name
-        );
-        // Only push if there are actually benchmarks found
-        if bf.benches.len() != 0 {
+            println!(
+                "found {} benchmark(s) for {}",
+                bf.benches.len(),
+                product_name
+            );
             bench_files.push(bf)
         }
     }
@@ -190,6 +329,7 @@ pub fn find_benchmarks_for_project(project_name: &str) -> Project {
     let proj = Project {
         name: project_name.to_string(),
         bench_files,
+        listing_failures,
     };
 
     return proj;
@@ -213,6 +353,38 @@ pub fn read_target_projects() -> Vec<TargetProject> {
         .collect()
 }
 
+/// Print how many benchmarks every project contributes, and why bench targets were left out.
+pub(crate) fn print_listing_summary(projects: &[Project]) {
+    println!("\nSummary:");
+    for project in projects {
+        println!(
+            "{}: {} benchmarks in {} bench files, {} bench files left out",
+            project.name,
+            project
+                .bench_files
+                .iter()
+                .map(|bench_file| bench_file.benches.len())
+                .sum::<usize>(),
+            project.bench_files.len(),
+            project.listing_failures.len()
+        );
+        for failure in &project.listing_failures {
+            let status = failure
+                .status
+                .map_or(String::new(), |status| format!(", exit code {}", status));
+            let bench_file = if failure.bench_file.is_empty() {
+                "all bench files"
+            } else {
+                &failure.bench_file
+            };
+            println!("  {}: {}{}", bench_file, failure.kind, status);
+            for line in failure.excerpt.lines() {
+                println!("    {}", line);
+            }
+        }
+    }
+}
+
 pub(crate) fn find_all_benchmarks() -> Vec<Project> {
     let target_projects = read_target_projects();
     target_projects
@@ -237,22 +409,24 @@ fn get_git_project(project: TargetProject) -> ExitStatus {
 #[test]
 fn run_find_benchmarks_for_project() {
     let project = find_benchmarks_for_project("prost");
-    project.store().unwrap()
+    crate::data::registry::Registry::open_default().store(&project)
 }
 
 #[test]
 fn parse_all_from_targets() {
+    let registry = crate::data::registry::Registry::open_default();
     find_all_benchmarks()
         .iter()
-        .for_each(|project| project.store().expect("Could not store project"));
+        .for_each(|project| registry.store(project));
 }
 
 #[test]
 fn count_benches() {
     let num_projects = read_target_projects().len();
+    let registry = crate::data::registry::Registry::open_default();
     let sum: usize = read_target_projects()
         .iter()
-        .map(|target| Project::load(&target.name).unwrap())
+        .map(|target| registry.load(&target.name).unwrap())
         .map(|project| {
             project
                 .bench_files
@@ -266,9 +440,10 @@ fn count_benches() {
 
 #[test]
 fn benches_length() {
+    let registry = crate::data::registry::Registry::open_default();
     read_target_projects()
         .iter()
-        .map(|target| Project::load(&target.name).unwrap())
+        .map(|target| registry.load(&target.name).unwrap())
         .for_each(|project| {
             println!(
                 "{}: {}",
@@ -295,7 +470,7 @@ pub(crate) fn cargo_check_all_projects() {
     for project in read_target_projects() {
         let success = Command::new("cargo")
             .current_dir(get_workdir_for_project(&project.name))
-            .args(&["check", "--benches", "--quiet"])
+            .args(&["check", "--workspace", "--benches", "--quiet"])
             .output()
             .unwrap()
             .status
@@ -306,9 +481,32 @@ pub(crate) fn cargo_check_all_projects() {
                 "{:?}",
                 Command::new("cargo")
                     .current_dir(get_workdir_for_project(&project.name))
-                    .args(&["check", "--benches"])
+                    .args(&["check", "--workspace", "--benches"])
             );
         }
     }
     println!("Done");
 }
+
+#[test]
+fn test_classify_listing() {
+    let compile_error = "   Compiling chrono v0.4.24\nerror[E0425]: cannot find value `x` in this scope\n --> benches/chrono.rs:3:5\nerror: could not compile `chrono` due to previous error";
+    assert_eq!(
+        classify_listing(false, "", compile_error, 0),
+        Some(ListingFailureKind::CompileError)
+    );
+    assert!(excerpt(compile_error).starts_with("error[E0425]"));
+    assert_eq!(
+        classify_listing(false, "", "thread 'main' panicked", 0),
+        Some(ListingFailureKind::Error)
+    );
+    assert_eq!(
+        classify_listing(true, "Benchmarking is not supported\n", "", 0),
+        Some(ListingFailureKind::UnexpectedOutput)
+    );
+    assert_eq!(
+        classify_listing(true, "0 tests, 0 benchmarks\n", "", 0),
+        Some(ListingFailureKind::NoBenchmarks)
+    );
+    assert_eq!(classify_listing(true, "parse: bench\n", "", 1), None);
+}
@@ -0,0 +1,287 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::data::project::{read_target_projects, BenchFile, Project};
+use crate::manifest::{self, ProjectRevision};
+
+/// Projects found by `power project parse` are kept as `registry/<project>.json`
+pub const REGISTRY_DIR: &str = "registry";
+
+/// Version 1 is a bare project as it was written to `<project>.json` in the working directory
+pub const SCHEMA_VERSION: u64 = 2;
+
+/// A project with when and from which commit its benchmarks were discovered.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub schema_version: u64,
+    /// `None` for projects migrated from version 1
+    pub discovered: Option<String>,
+    pub commit: Option<String>,
+    pub dirty: Option<bool>,
+    pub project: Project,
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Missing(String),
+    Invalid(PathBuf, String),
+    /// Written by a newer version of `power`
+    UnknownVersion(PathBuf, u64),
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryError::Missing(project) => write!(
+                f,
+                "{} is not in the registry, run `power project parse`",
+                project
+            ),
+            RegistryError::Invalid(path, err) => write!(f, "could not parse {:?}: {}", path, err),
+            RegistryError::UnknownVersion(path, version) => write!(
+                f,
+                "{:?} has schema version {}, this version of power reads up to {}",
+                path, version, SCHEMA_VERSION
+            ),
+        }
+    }
+}
+
+/// Bring an entry of any older schema version up to `SCHEMA_VERSION`, one version at a time.
+fn migrate(mut value: Value, path: &Path) -> Result<Entry, RegistryError> {
+    let mut version = value
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(1);
+    if version > SCHEMA_VERSION {
+        return Err(RegistryError::UnknownVersion(path.to_path_buf(), version));
+    }
+    while version < SCHEMA_VERSION {
+        value = match version {
+            1 => json!({
+                "schema_version": 2,
+                "discovered": null,
+                "commit": null,
+                "dirty": null,
+                "project": value,
+            }),
+            _ => unreachable!("No migration from schema version {}", version),
+        };
+        version += 1;
+    }
+    serde_json::from_value(value)
+        .map_err(|err| RegistryError::Invalid(path.to_path_buf(), err.to_string()))
+}
+
+/// The projects whose benchmarks were discovered, the run, coverage and stats commands read them from here.
+pub struct Registry {
+    dir: PathBuf,
+    /// Where version 1 projects were written, they are copied into the registry when loaded
+    legacy_dir: PathBuf,
+}
+
+impl Registry {
+    pub fn open(dir: &Path, legacy_dir: &Path) -> Registry {
+        Registry {
+            dir: dir.to_path_buf(),
+            legacy_dir: legacy_dir.to_path_buf(),
+        }
+    }
+
+    pub fn open_default() -> Registry {
+        Registry::open(Path::new(REGISTRY_DIR), Path::new("."))
+    }
+
+    pub fn path(&self, project: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", project))
+    }
+
+    fn write(&self, entry: &Entry) {
+        fs::create_dir_all(&self.dir)
+            .unwrap_or_else(|err| panic!("Could not create registry {:?}: {}", self.dir, err));
+        let path = self.path(&entry.project.name);
+        fs::write(&path, serde_json::to_string_pretty(entry).unwrap())
+            .unwrap_or_else(|err| panic!("Could not write {:?}: {}", path, err));
+    }
+
+    /// Store freshly discovered benchmarks with the commit of the project.
+    pub fn store(&self, project: &Project) {
+        let revision = ProjectRevision::capture(&project.name);
+        self.write(&Entry {
+            schema_version: SCHEMA_VERSION,
+            discovered: Some(manifest::now()),
+            commit: revision.commit,
+            dirty: revision.dirty,
+            project: project.clone(),
+        });
+    }
+
+    pub fn entry(&self, project: &str) -> Result<Entry, RegistryError> {
+        let path = self.path(project);
+        let legacy = self.legacy_dir.join(format!("{}.json", project));
+        let (path, migrating) = if path.exists() {
+            (path, false)
+        } else if legacy.exists() {
+            (legacy, true)
+        } else {
+            return Err(RegistryError::Missing(project.to_string()));
+        };
+
+        let value = fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|content| {
+                serde_json::from_str::<Value>(&content).map_err(|err| err.to_string())
+            })
+            .map_err(|err| RegistryError::Invalid(path.clone(), err))?;
+        let entry = migrate(value, &path)?;
+        if migrating {
+            println!(
+                "Migrated {:?} into the registry at {:?}",
+                path,
+                self.path(project)
+            );
+            self.write(&entry);
+        }
+        Ok(entry)
+    }
+
+    pub fn load(&self, project: &str) -> Result<Project, RegistryError> {
+        self.entry(project).map(|entry| entry.project)
+    }
+
+    /// Every project in the registry, by name.
+    pub fn projects(&self) -> Vec<String> {
+        let mut projects = fs::read_dir(&self.dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .filter(|path| {
+                        path.extension()
+                            .map_or(false, |extension| extension == "json")
+                    })
+                    .map(|path| path.file_stem().unwrap().to_string_lossy().to_string())
+                    .collect::<Vec<String>>()
+            })
+            .unwrap_or_default();
+        projects.sort();
+        projects
+    }
+
+    /// The projects in `targets.csv`, in its order, with the reason a project can not be used.
+    pub fn load_targets(&self) -> Vec<(String, Result<Project, RegistryError>)> {
+        read_target_projects()
+            .into_iter()
+            .map(|target| {
+                let project = self.load(&target.name);
+                (target.name, project)
+            })
+            .collect()
+    }
+
+    /// The projects in `targets.csv` that can be benchmarked, the others are reported and left out.
+    pub fn targets(&self) -> Vec<Project> {
+        self.load_targets()
+            .into_iter()
+            .filter_map(|(name, project)| match project {
+                Ok(project) => Some(project),
+                Err(err) => {
+                    println!("Warning: leaving out {}, {}", name, err);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn print(&self) {
+        for project in self.projects() {
+            match self.entry(&project) {
+                Ok(entry) => println!(
+                    "{}: {} bench files, {} benchmarks, discovered {} at {}{}",
+                    project,
+                    entry.project.bench_files.len(),
+                    entry
+                        .project
+                        .bench_files
+                        .iter()
+                        .map(|bench_file| bench_file.benches.len())
+                        .sum::<usize>(),
+                    entry.discovered.as_deref().unwrap_or("before the registry"),
+                    entry.commit.as_deref().unwrap_or("an unknown commit"),
+                    if entry.dirty == Some(true) {
+                        " (dirty)"
+                    } else {
+                        ""
+                    }
+                ),
+                Err(err) => println!("{}: {}", project, err),
+            }
+        }
+    }
+
+    pub fn bench_files(&self, project: &str) -> Result<Vec<BenchFile>, RegistryError> {
+        self.load(project).map(|project| project.bench_files)
+    }
+
+    /// `(bench file, benchmark id)` of every benchmark of the project.
+    pub fn benchmarks(&self, project: &str) -> Result<Vec<(String, String)>, RegistryError> {
+        Ok(self
+            .bench_files(project)?
+            .into_iter()
+            .flat_map(|bench_file| {
+                bench_file
+                    .benches
+                    .into_iter()
+                    .map(move |id| (bench_file.name.clone(), id))
+            })
+            .collect())
+    }
+}
+
+#[test]
+fn test_registry() {
+    let dir = tempfile::tempdir().unwrap();
+    let registry = Registry::open(&dir.path().join(REGISTRY_DIR), dir.path());
+    assert!(matches!(
+        registry.load("chrono"),
+        Err(RegistryError::Missing(_))
+    ));
+
+    // A project written by an earlier version of `power project parse`
+    fs::write(
+        dir.path().join("chrono.json"),
+        r#"{"name":"chrono","bench_files":[{"project":"chrono","name":"chrono","source":"benches/chrono.rs","features":[],"benches":["bench_datetime_parse","bench_year_flags"]}]}"#,
+    )
+    .unwrap();
+    let entry = registry.entry("chrono").unwrap();
+    assert_eq!(entry.schema_version, SCHEMA_VERSION);
+    assert_eq!(entry.discovered, None);
+    assert!(registry.path("chrono").exists());
+    assert_eq!(registry.projects(), vec!["chrono".to_string()]);
+    assert_eq!(
+        registry.benchmarks("chrono").unwrap(),
+        vec![
+            ("chrono".to_string(), "bench_datetime_parse".to_string()),
+            ("chrono".to_string(), "bench_year_flags".to_string())
+        ]
+    );
+
+    registry.store(&Project {
+        name: "regex".to_string(),
+        bench_files: vec![],
+        listing_failures: vec![],
+    });
+    let entry = registry.entry("regex").unwrap();
+    assert!(entry.discovered.is_some());
+    assert_eq!(entry.project.name, "regex");
+
+    fs::write(registry.path("regex"), r#"{"schema_version": 3}"#).unwrap();
+    assert!(matches!(
+        registry.load("regex"),
+        Err(RegistryError::UnknownVersion(_, 3))
+    ));
+}
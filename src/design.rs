@@ -0,0 +1,209 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use crate::journal::BenchCommand;
+
+/// The order the benchmarks run in, every design is reproducible from the seed of the run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Design {
+    /// Every repetition runs all benchmarks in a new random order
+    #[default]
+    Rmit,
+    /// Every repetition runs the projects in a random order, and the benchmarks
+    /// of a project in a random order one after the other
+    Blocked,
+    /// Williams design: every benchmark is followed by every other benchmark equally
+    /// often, which balances out the carry-over of one benchmark on the next
+    LatinSquare,
+    /// Every benchmark runs all its repetitions in a row, see `run_project_consecutive`
+    Consecutive,
+}
+
+impl FromStr for Design {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rmit" => Ok(Design::Rmit),
+            "blocked" => Ok(Design::Blocked),
+            "latin-square" => Ok(Design::LatinSquare),
+            "consecutive" => Ok(Design::Consecutive),
+            _ => Err(format!(
+                "Expected `rmit`, `blocked`, `latin-square` or `consecutive`, got `{}`",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for Design {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Design::Rmit => write!(f, "rmit"),
+            Design::Blocked => write!(f, "blocked"),
+            Design::LatinSquare => write!(f, "latin-square"),
+            Design::Consecutive => write!(f, "consecutive"),
+        }
+    }
+}
+
+/// Rows of a Williams design for `n` benchmarks. For an odd `n` the mirrored rows are
+/// needed as well, so every ordered pair of neighbours occurs in exactly two rows.
+pub fn williams(n: usize) -> Vec<Vec<usize>> {
+    // 0, 1, n - 1, 2, n - 2, ...
+    let first = (0..n)
+        .map(|j| {
+            if j % 2 == 1 {
+                (j + 1) / 2
+            } else {
+                (n - j / 2) % n
+            }
+        })
+        .collect::<Vec<usize>>();
+    let mut rows = (0..n)
+        .map(|row| first.iter().map(|j| (j + row) % n).collect::<Vec<usize>>())
+        .collect::<Vec<_>>();
+    if n % 2 == 1 {
+        let mirrored = rows
+            .iter()
+            .map(|row| row.iter().rev().cloned().collect())
+            .collect::<Vec<_>>();
+        rows.extend(mirrored);
+    }
+    rows
+}
+
+/// Project of a command named `project/bench file/benchmark id`
+fn project_of(command: &BenchCommand) -> &str {
+    command.name.split('/').next().unwrap()
+}
+
+impl Design {
+    /// The number of repetitions the design is balanced for, they should be a multiple of it.
+    pub fn period(self, benchmarks: usize) -> usize {
+        match self {
+            Design::LatinSquare => williams(benchmarks).len().max(1),
+            _ => 1,
+        }
+    }
+
+    /// The order of the commands in repetition `iteration` of a run with `seed`.
+    pub fn order(
+        self,
+        commands: &[BenchCommand],
+        seed: u64,
+        iteration: usize,
+    ) -> Vec<BenchCommand> {
+        let mut rng = StdRng::seed_from_u64(seed.wrapping_add(iteration as u64));
+        match self {
+            Design::Rmit => {
+                let mut order = commands.to_vec();
+                order.shuffle(&mut rng);
+                order
+            }
+            Design::Blocked => {
+                let mut projects: Vec<Vec<BenchCommand>> = vec![];
+                for command in commands {
+                    match projects
+                        .iter_mut()
+                        .find(|block| project_of(&block[0]) == project_of(command))
+                    {
+                        Some(block) => block.push(command.clone()),
+                        None => projects.push(vec![command.clone()]),
+                    }
+                }
+                projects.shuffle(&mut rng);
+                for block in projects.iter_mut() {
+                    block.shuffle(&mut rng);
+                }
+                projects.concat()
+            }
+            Design::LatinSquare => {
+                // The same random labelling and order of rows in every repetition of the run
+                let mut rng = StdRng::seed_from_u64(seed);
+                let mut labels = commands.to_vec();
+                labels.shuffle(&mut rng);
+                let mut rows = williams(commands.len());
+                rows.shuffle(&mut rng);
+                match rows.get(iteration % rows.len().max(1)) {
+                    Some(row) => row.iter().map(|&label| labels[label].clone()).collect(),
+                    None => vec![],
+                }
+            }
+            Design::Consecutive => commands.to_vec(),
+        }
+    }
+}
+
+#[test]
+fn test_williams() {
+    for n in 1..=7 {
+        let rows = williams(n);
+        assert_eq!(rows.len(), if n % 2 == 0 { n } else { 2 * n });
+        let mut neighbours = vec![vec![0; n]; n];
+        for row in &rows {
+            let mut sorted = row.clone();
+            sorted.sort();
+            assert_eq!(sorted, (0..n).collect::<Vec<usize>>());
+            for pair in row.windows(2) {
+                neighbours[pair[0]][pair[1]] += 1;
+            }
+        }
+        let expected = if n % 2 == 0 { 1 } else { 2 };
+        for a in 0..n {
+            for b in 0..n {
+                assert_eq!(neighbours[a][b], if a == b { 0 } else { expected });
+            }
+        }
+    }
+}
+
+#[test]
+fn test_order() {
+    let commands = ["a/b/1", "a/b/2", "b/b/1", "b/b/2", "c/b/1"]
+        .iter()
+        .map(|name| BenchCommand {
+            name: name.to_string(),
+            executable: String::new(),
+            benchmark: name.to_string(),
+            workdir: Default::default(),
+            harness: Default::default(),
+        })
+        .collect::<Vec<_>>();
+    let names = |order: Vec<BenchCommand>| {
+        order
+            .into_iter()
+            .map(|command| command.name)
+            .collect::<Vec<String>>()
+    };
+
+    for design in [Design::Rmit, Design::Blocked, Design::LatinSquare] {
+        // Reproducible from the seed
+        assert_eq!(
+            names(design.order(&commands, 42, 3)),
+            names(design.order(&commands, 42, 3))
+        );
+        let mut sorted = names(design.order(&commands, 42, 3));
+        sorted.sort();
+        assert_eq!(sorted, names(commands.clone()));
+    }
+
+    // The benchmarks of a project run one after the other
+    let blocked = names(Design::Blocked.order(&commands, 7, 0));
+    let projects = blocked.iter().map(|name| &name[..1]).collect::<Vec<&str>>();
+    let blocks = projects
+        .windows(2)
+        .filter(|pair| pair[0] != pair[1])
+        .count()
+        + 1;
+    assert_eq!(blocks, 3);
+
+    assert_eq!(Design::LatinSquare.period(commands.len()), 10);
+    assert_eq!("latin-square".parse::<Design>(), Ok(Design::LatinSquare));
+}
@@ -0,0 +1,291 @@
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+
+use crate::harness::HarnessKind;
+
+/// Output of every benchmark execution is kept in `logs/<run id>/<iteration>/`
+pub const LOG_DIR: &str = "logs";
+
+/// Process group of the benchmark that is currently running, 0 if none is
+static RUNNING: AtomicI32 = AtomicI32::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailureKind {
+    CompileError,
+    Panic,
+    Timeout,
+    Signal,
+    /// Non-zero exit code without a panic
+    Error,
+    /// Criterion could not keep to the settings, the results are kept
+    CriterionWarning,
+}
+
+impl FailureKind {
+    /// Whether the benchmark has no usable results
+    pub fn is_fatal(self) -> bool {
+        self != FailureKind::CriterionWarning
+    }
+
+    /// Whether running the benchmark again can get rid of the failure
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            FailureKind::Panic | FailureKind::Timeout | FailureKind::Signal | FailureKind::Error
+        )
+    }
+
+    /// Parse a failure kind for `--retry-on`, which only takes the retryable kinds.
+    pub fn parse_retryable(s: &str) -> Result<Self, String> {
+        let kind = s.parse::<FailureKind>()?;
+        if !kind.is_retryable() {
+            return Err(format!(
+                "Expected `panic`, `timeout`, `signal` or `error`, `{}` can not be retried",
+                kind
+            ));
+        }
+        Ok(kind)
+    }
+}
+
+impl FromStr for FailureKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "compile-error" => Ok(FailureKind::CompileError),
+            "panic" => Ok(FailureKind::Panic),
+            "timeout" => Ok(FailureKind::Timeout),
+            "signal" => Ok(FailureKind::Signal),
+            "error" => Ok(FailureKind::Error),
+            "criterion-warning" => Ok(FailureKind::CriterionWarning),
+            _ => Err(format!(
+                "Expected `compile-error`, `panic`, `timeout`, `signal`, `error` or `criterion-warning`, got `{}`",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for FailureKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FailureKind::CompileError => write!(f, "compile-error"),
+            FailureKind::Panic => write!(f, "panic"),
+            FailureKind::Timeout => write!(f, "timeout"),
+            FailureKind::Signal => write!(f, "signal"),
+            FailureKind::Error => write!(f, "error"),
+            FailureKind::CriterionWarning => write!(f, "criterion-warning"),
+        }
+    }
+}
+
+/// One failed or suspicious execution of a benchmark.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Failure {
+    /// `project/bench file/benchmark id`
+    pub name: String,
+    pub attempt: usize,
+    pub kind: FailureKind,
+    pub message: String,
+    /// The stderr log, `None` when the benchmark never ran
+    pub log: Option<PathBuf>,
+}
+
+/// A finished benchmark process.
+pub struct Execution {
+    pub name: String,
+    pub attempt: usize,
+    pub status: ExitStatus,
+    pub timed_out: bool,
+    pub stdout: String,
+    pub stderr: String,
+    pub log: PathBuf,
+}
+
+/// File name for the logs of a benchmark, ids contain slashes and spaces
+fn log_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Run a benchmark in its own process group with its output written to `log_dir`,
+/// after `timeout` the whole group is killed, including everything it started.
+pub fn execute(
+    command: &mut Command,
+    timeout: Option<Duration>,
+    log_dir: &Path,
+    name: &str,
+    attempt: usize,
+) -> Execution {
+    fs::create_dir_all(log_dir)
+        .unwrap_or_else(|err| panic!("Could not create log directory {:?}: {}", log_dir, err));
+    let stdout_path = log_dir.join(format!("{}.{}.stdout", log_name(name), attempt));
+    let stderr_path = log_dir.join(format!("{}.{}.stderr", log_name(name), attempt));
+    let create = |path: &Path| {
+        File::create(path).unwrap_or_else(|err| panic!("Could not create log {:?}: {}", path, err))
+    };
+
+    let mut child = command
+        .stdout(create(&stdout_path))
+        .stderr(create(&stderr_path))
+        .process_group(0)
+        .spawn()
+        .unwrap_or_else(|err| panic!("Could not start {:?}: {}", command, err));
+    let group = child.id() as i32;
+    RUNNING.store(group, Ordering::SeqCst);
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || sender.send(child.wait()));
+    let (status, timed_out) = match timeout.map(|timeout| receiver.recv_timeout(timeout)) {
+        Some(Ok(status)) => (status, false),
+        Some(Err(_)) => {
+            kill_running();
+            (receiver.recv().unwrap(), true)
+        }
+        None => (receiver.recv().unwrap(), false),
+    };
+    RUNNING.store(0, Ordering::SeqCst);
+
+    Execution {
+        name: name.to_string(),
+        attempt,
+        status: status.unwrap_or_else(|err| panic!("Could not wait for {}: {}", name, err)),
+        timed_out,
+        stdout: fs::read_to_string(&stdout_path).unwrap_or_default(),
+        stderr: fs::read_to_string(&stderr_path).unwrap_or_default(),
+        log: stderr_path,
+    }
+}
+
+/// Kill the process group of the running benchmark, if any.
+pub fn kill_running() {
+    let group = RUNNING.load(Ordering::SeqCst);
+    if group != 0 {
+        let _ = killpg(Pid::from_raw(group), Signal::SIGKILL);
+    }
+}
+
+impl Execution {
+    /// The failure of the execution, warnings only count for the harnesses that report them.
+    pub fn failure(&self, harness: HarnessKind) -> Option<Failure> {
+        let line = |pattern: &str| {
+            self.stderr
+                .lines()
+                .chain(self.stdout.lines())
+                .find(|line| line.contains(pattern))
+                .map(|line| line.trim().to_string())
+        };
+
+        let (kind, message) = if self.timed_out {
+            (FailureKind::Timeout, "killed after the timeout".to_string())
+        } else if let Some(signal) = self.status.signal() {
+            (FailureKind::Signal, format!("killed by signal {}", signal))
+        } else if let Some(panic) = line("panicked at") {
+            (FailureKind::Panic, panic)
+        } else if !self.status.success() {
+            (
+                FailureKind::Error,
+                format!("exited with {}", self.status.code().unwrap_or_default()),
+            )
+        } else if let Some(warning) = line("Warning:").filter(|_| harness == HarnessKind::Criterion) {
+            (FailureKind::CriterionWarning, warning)
+        } else {
+            return None;
+        };
+
+        Some(Failure {
+            name: self.name.clone(),
+            attempt: self.attempt,
+            kind,
+            message,
+            log: Some(self.log.clone()),
+        })
+    }
+}
+
+#[test]
+fn test_failure() {
+    let execution = |raw: i32, timed_out: bool, stderr: &str| Execution {
+        name: "project/bench/id".to_string(),
+        attempt: 1,
+        status: ExitStatus::from_raw(raw),
+        timed_out,
+        stdout: String::new(),
+        stderr: stderr.to_string(),
+        log: PathBuf::from("logs/run/1/project_bench_id.1.stderr"),
+    };
+    let kind = |execution: Execution| {
+        execution
+            .failure(HarnessKind::Criterion)
+            .map(|failure| failure.kind)
+    };
+
+    assert_eq!(kind(execution(0, false, "")), None);
+    assert_eq!(kind(execution(9, true, "")), Some(FailureKind::Timeout));
+    // Wait statuses: the exit code is in the second byte, a signal in the first
+    assert_eq!(kind(execution(11, false, "")), Some(FailureKind::Signal));
+    assert_eq!(
+        kind(execution(
+            101 << 8,
+            false,
+            "thread 'main' panicked at 'index out of bounds', benches/b.rs:3:5"
+        )),
+        Some(FailureKind::Panic)
+    );
+    assert_eq!(kind(execution(1 << 8, false, "")), Some(FailureKind::Error));
+    let warning = execution(
+        0,
+        false,
+        "Warning: Unable to complete 300 samples in 30.0s. You may wish to increase target time to 41.2s.",
+    );
+    assert_eq!(warning.failure(HarnessKind::Libtest).map(|failure| failure.kind), None);
+    let warning = warning.failure(HarnessKind::Criterion).unwrap();
+    assert_eq!(warning.kind, FailureKind::CriterionWarning);
+    assert!(!warning.kind.is_fatal());
+    assert!(!warning.kind.is_retryable());
+    assert_eq!(FailureKind::parse_retryable("panic"), Ok(FailureKind::Panic));
+    assert!(FailureKind::parse_retryable("compile-error").is_err());
+    assert!(FailureKind::parse_retryable("criterion-warning").is_err());
+    assert_eq!(log_name("project/bench file/id"), "project_bench_file_id");
+}
+
+#[test]
+fn test_timeout() {
+    let dir = tempfile::tempdir().unwrap();
+    let execution = execute(
+        Command::new("sh").args(["-c", "echo started; sleep 10 & wait"]),
+        Some(Duration::from_millis(200)),
+        dir.path(),
+        "project/bench/id",
+        1,
+    );
+    assert!(execution.timed_out);
+    assert_eq!(execution.stdout, "started\n");
+    assert_eq!(
+        execution
+            .failure(HarnessKind::Libtest)
+            .map(|failure| failure.kind),
+        Some(FailureKind::Timeout)
+    );
+}
@@ -0,0 +1,76 @@
+use std::path::Path;
+
+use crate::data::criterion::{BenchmarkId, SampleData};
+use crate::harness::{Harness, Measurement};
+use crate::stats::parse::read_json;
+
+/// Criterion, which writes its samples to `target/criterion/<benchmark>/new/`.
+pub struct Criterion;
+
+impl Harness for Criterion {
+    fn results_dir(&self) -> &'static str {
+        "criterion"
+    }
+
+    fn list_args(&self) -> Vec<String> {
+        vec!["--list".to_string()]
+    }
+
+    fn bench_args(
+        &self,
+        benchmark: &str,
+        measurement_time: u64,
+        warmup_time: u64,
+        sample_size: u64,
+    ) -> Vec<String> {
+        vec![
+            "--bench".to_string(),
+            "--measurement-time".to_string(),
+            measurement_time.to_string(),
+            "--warm-up-time".to_string(),
+            warmup_time.to_string(),
+            "--sample-size".to_string(),
+            sample_size.to_string(),
+            // Criterion uses a regex to select benchmarks,
+            // so we do this to prevent selecting multiple benchmarks to run
+            format!("^{}$", benchmark),
+        ]
+    }
+
+    fn keeps_stdout(&self) -> bool {
+        false
+    }
+
+    fn parse_results(&self, dir: &Path) -> Vec<Measurement> {
+        let pattern = dir.join("**").join("new").join("benchmark.json");
+
+        let mut measurements = vec![];
+        for entry in glob::glob(&pattern.to_string_lossy()).expect("Invalid glob pattern") {
+            let benchmark_json = match entry {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+            let dir = benchmark_json.parent().unwrap();
+
+            let id: BenchmarkId = match read_json(&benchmark_json) {
+                Some(id) => id,
+                None => continue,
+            };
+            let samples: SampleData = match read_json(&dir.join("sample.json")) {
+                Some(samples) => samples,
+                None => {
+                    println!("No samples for {} in {:?}", id.full_id, dir);
+                    continue;
+                }
+            };
+
+            measurements.push(Measurement {
+                estimates: read_json(&dir.join("estimates.json")),
+                id,
+                samples,
+                bench_file: None,
+            });
+        }
+        measurements
+    }
+}
@@ -0,0 +1,114 @@
+use std::path::Path;
+
+use crate::harness::{parse_stdout_files, Harness, Measurement};
+
+/// Divan, which prints a tree of benchmarks with their fastest, slowest, median and mean time.
+pub struct Divan;
+
+const TREE: [char; 5] = ['│', '├', '╰', '─', ' '];
+
+/// A time like `1.5 µs` in nanoseconds
+fn parse_time(time: &str) -> Option<f64> {
+    let (value, unit) = time.trim().split_once(' ')?;
+    let scale = match unit {
+        "ps" => 1e-3,
+        "ns" => 1.0,
+        "µs" | "us" => 1e3,
+        "ms" => 1e6,
+        "s" => 1e9,
+        _ => return None,
+    };
+    Some(value.parse::<f64>().ok()? * scale)
+}
+
+/// `(benchmark path, median nanoseconds per iteration)` for every row with a median
+fn parse_output(output: &str) -> Vec<(String, f64)> {
+    let mut results = vec![];
+    let mut path: Vec<String> = vec![];
+    let mut in_table = false;
+    for line in output.lines() {
+        // The header starts with the name of the bench file
+        if line.contains("fastest") && line.contains("median") {
+            in_table = true;
+            path.clear();
+            continue;
+        }
+        if !in_table || line.trim().is_empty() {
+            continue;
+        }
+
+        // Every level of the tree is indented by three characters
+        let indent = line.chars().take_while(|c| TREE.contains(c)).count();
+        let row = line.chars().skip(indent).collect::<String>();
+        let columns = row.split('│').collect::<Vec<&str>>();
+        let name = columns[0].split("  ").next().unwrap().trim();
+        if name.is_empty() || indent < 3 {
+            continue;
+        }
+        path.truncate(indent / 3 - 1);
+        path.push(name.to_string());
+
+        if let Some(median) = columns.get(2).and_then(|median| parse_time(median)) {
+            results.push((path.join("::"), median));
+        }
+    }
+    results
+}
+
+impl Harness for Divan {
+    fn results_dir(&self) -> &'static str {
+        "divan"
+    }
+
+    fn list_args(&self) -> Vec<String> {
+        vec!["--list".to_string()]
+    }
+
+    /// Divan has no warm-up setting
+    fn bench_args(
+        &self,
+        benchmark: &str,
+        measurement_time: u64,
+        _: u64,
+        sample_size: u64,
+    ) -> Vec<String> {
+        vec![
+            "--bench".to_string(),
+            "--exact".to_string(),
+            benchmark.to_string(),
+            "--sample-count".to_string(),
+            sample_size.to_string(),
+            "--max-time".to_string(),
+            measurement_time.to_string(),
+        ]
+    }
+
+    /// Stops after `--max-time` at the latest
+    fn estimated_seconds(&self, measurement_time: u64, _: u64, _: u64) -> Option<f64> {
+        Some(measurement_time as f64)
+    }
+
+    fn parse_results(&self, dir: &Path) -> Vec<Measurement> {
+        parse_stdout_files(dir, parse_output)
+    }
+}
+
+#[test]
+fn test_parse_output() {
+    let output = "\
+Timer precision: 20 ns
+sorting          fastest       │ slowest       │ median        │ mean          │ samples │ iters
+├─ sort_small    101.2 ns      │ 150.3 ns      │ 105.5 ns      │ 107.1 ns      │ 100     │ 3200
+╰─ vec                         │               │               │               │         │
+   ├─ 10         1.05 µs       │ 1.3 µs        │ 1.1 µs        │ 1.12 µs       │ 100     │ 400
+   ╰─ 1000       2.001 ms      │ 2.5 ms        │ 2.25 ms       │ 2.3 ms        │ 100     │ 100
+";
+    assert_eq!(
+        parse_output(output),
+        vec![
+            ("sort_small".to_string(), 105.5),
+            ("vec::10".to_string(), 1100.0),
+            ("vec::1000".to_string(), 2_250_000.0)
+        ]
+    );
+}
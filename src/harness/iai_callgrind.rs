@@ -0,0 +1,116 @@
+use std::path::Path;
+
+use crate::harness::{parse_stdout_files, Harness, Measurement, Unit};
+
+/// iai-callgrind, which counts instructions under Callgrind instead of measuring time.
+/// It can not list or select single benchmarks, so a bench file runs as a whole and
+/// its benchmark ids are learned from running it once.
+pub struct IaiCallgrind;
+
+/// `(benchmark, estimated cycles)`, the benchmark is the unindented line above the counts
+fn parse_output(output: &str) -> Vec<(String, f64)> {
+    let mut results = vec![];
+    let mut benchmark = None;
+    for line in output.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !line.starts_with(char::is_whitespace) {
+            benchmark = Some(line.trim().to_string());
+            continue;
+        }
+        // `  Estimated Cycles:   2676|2670   (+0.22472%)`, the old value is from the previous run
+        let (key, value) = match line.trim().split_once(':') {
+            Some(pair) => pair,
+            None => continue,
+        };
+        if key.trim() != "Estimated Cycles" {
+            continue;
+        }
+        let cycles = value
+            .trim()
+            .split(|c: char| c == '|' || c.is_whitespace())
+            .next()
+            .and_then(|cycles| cycles.parse().ok());
+        if let (Some(benchmark), Some(cycles)) = (&benchmark, cycles) {
+            results.push((benchmark.clone(), cycles));
+        }
+    }
+    results
+}
+
+impl Harness for IaiCallgrind {
+    fn results_dir(&self) -> &'static str {
+        "iai-callgrind"
+    }
+
+    /// Nothing lists the benchmarks, running them is the only way to learn their ids
+    fn list_args(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn parse_list(&self, output: &str) -> Vec<String> {
+        let mut ids: Vec<String> = vec![];
+        for (id, _) in parse_output(output) {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    fn selects_benchmarks(&self) -> bool {
+        false
+    }
+
+    /// Runs the whole bench file, Callgrind needs no time settings
+    fn bench_args(&self, _: &str, _: u64, _: u64, _: u64) -> Vec<String> {
+        vec!["--bench".to_string()]
+    }
+
+    /// Depends on how slow the bench file is under Callgrind
+    fn estimated_seconds(&self, _: u64, _: u64, _: u64) -> Option<f64> {
+        None
+    }
+
+    fn unit(&self) -> Unit {
+        Unit::EstimatedCycles
+    }
+
+    fn parse_results(&self, dir: &Path) -> Vec<Measurement> {
+        parse_stdout_files(dir, parse_output)
+    }
+}
+
+#[test]
+fn test_parse_output() {
+    let output = "\
+my_benchmark::bench_group::bench_fibonacci short:10
+  Instructions:                1734|N/A             (*********)
+  L1 Hits:                     2359|N/A             (*********)
+  Estimated Cycles:            2941|N/A             (*********)
+my_benchmark::bench_group::bench_fibonacci long:30
+  Instructions:            26214734|26214734        (No change)
+  Estimated Cycles:        35092834|35092800        (+0.00010%)
+";
+    assert_eq!(
+        parse_output(output),
+        vec![
+            (
+                "my_benchmark::bench_group::bench_fibonacci short:10".to_string(),
+                2941.0
+            ),
+            (
+                "my_benchmark::bench_group::bench_fibonacci long:30".to_string(),
+                35092834.0
+            )
+        ]
+    );
+    assert_eq!(
+        IaiCallgrind.parse_list(&format!("{}{}", output, output)),
+        vec![
+            "my_benchmark::bench_group::bench_fibonacci short:10".to_string(),
+            "my_benchmark::bench_group::bench_fibonacci long:30".to_string()
+        ]
+    );
+}
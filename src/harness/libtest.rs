@@ -0,0 +1,67 @@
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::harness::{parse_stdout_files, Harness, Measurement};
+
+/// The built-in `#[bench]` harness, which reports the median nanoseconds per iteration on stdout.
+pub struct Libtest;
+
+/// `(benchmark, nanoseconds per iteration)` for every `test <name> ... bench:` line
+fn parse_output(output: &str) -> Vec<(String, f64)> {
+    lazy_static! {
+        static ref RE_BENCH_RESULT: Regex =
+            Regex::new(r"(?m)^test (.+?)\s+\.\.\. bench:\s+([\d,]+(?:\.\d+)?) ns/iter").unwrap();
+    }
+    RE_BENCH_RESULT
+        .captures_iter(output)
+        .filter_map(|c| Some((c[1].to_string(), c[2].replace(',', "").parse().ok()?)))
+        .collect()
+}
+
+impl Harness for Libtest {
+    fn results_dir(&self) -> &'static str {
+        "libtest"
+    }
+
+    fn list_args(&self) -> Vec<String> {
+        vec!["--list".to_string()]
+    }
+
+    /// libtest decides on the measurement and sample size itself
+    fn bench_args(&self, benchmark: &str, _: u64, _: u64, _: u64) -> Vec<String> {
+        vec![
+            "--bench".to_string(),
+            "--exact".to_string(),
+            benchmark.to_string(),
+        ]
+    }
+
+    /// libtest stops sampling once the median is stable, or after at most three seconds
+    fn estimated_seconds(&self, _: u64, _: u64, _: u64) -> Option<f64> {
+        Some(3.0)
+    }
+
+    fn parse_results(&self, dir: &Path) -> Vec<Measurement> {
+        parse_stdout_files(dir, parse_output)
+    }
+}
+
+#[test]
+fn test_parse_output() {
+    let output = "
+running 2 tests
+test tests::bench_add ... bench:          12 ns/iter (+/- 1)
+test tests::bench_sort ... bench:   1,234,567.50 ns/iter (+/- 8,901.20)
+
+test result: ok. 0 passed; 0 failed; 0 ignored; 2 measured; 0 filtered out
+";
+    assert_eq!(
+        parse_output(output),
+        vec![
+            ("tests::bench_add".to_string(), 12.0),
+            ("tests::bench_sort".to_string(), 1_234_567.5)
+        ]
+    );
+}
@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::data::criterion::{BenchmarkId, Estimates, SampleData};
+
+pub(crate) mod criterion;
+pub(crate) mod divan;
+pub(crate) mod iai_callgrind;
+pub(crate) mod libtest;
+
+/// The benchmark harness a bench file is written for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HarnessKind {
+    #[default]
+    Criterion,
+    Libtest,
+    Divan,
+    IaiCallgrind,
+}
+
+impl HarnessKind {
+    pub const ALL: [HarnessKind; 4] = [
+        HarnessKind::Criterion,
+        HarnessKind::Libtest,
+        HarnessKind::Divan,
+        HarnessKind::IaiCallgrind,
+    ];
+
+    pub fn adapter(self) -> &'static dyn Harness {
+        match self {
+            HarnessKind::Criterion => &criterion::Criterion,
+            HarnessKind::Libtest => &libtest::Libtest,
+            HarnessKind::Divan => &divan::Divan,
+            HarnessKind::IaiCallgrind => &iai_callgrind::IaiCallgrind,
+        }
+    }
+
+    /// Recognise the harness from the source of a bench file. Without `harness = false`
+    /// in Cargo.toml the file is run by libtest, otherwise it has its own `main`.
+    pub fn detect(libtest_harness: bool, source: &str) -> Option<HarnessKind> {
+        if libtest_harness {
+            Some(HarnessKind::Libtest)
+        } else if source.contains("criterion_main!") {
+            Some(HarnessKind::Criterion)
+        } else if source.contains("divan::main") {
+            Some(HarnessKind::Divan)
+        } else if source.contains("iai_callgrind") {
+            Some(HarnessKind::IaiCallgrind)
+        } else {
+            None
+        }
+    }
+}
+
+/// What the values a harness reports are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Unit {
+    #[default]
+    Nanoseconds,
+    /// Estimated by Callgrind from instruction and cache counts
+    EstimatedCycles,
+}
+
+impl Display for Unit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Unit::Nanoseconds => write!(f, "nanoseconds"),
+            Unit::EstimatedCycles => write!(f, "estimated-cycles"),
+        }
+    }
+}
+
+/// One benchmark measured by a harness in one run.
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    pub id: BenchmarkId,
+    pub samples: SampleData,
+    pub estimates: Option<Estimates>,
+    /// The bench file, when the harness stores its results per bench file
+    pub bench_file: Option<String>,
+}
+
+/// Listing, running and reading the results of one kind of benchmark harness.
+pub trait Harness: Sync {
+    /// Directory in `target` with the results, moved to `data/<timestamp>/<project>/<dir>`
+    fn results_dir(&self) -> &'static str;
+
+    /// Arguments after `cargo bench --bench <file> --` that print the benchmark ids.
+    fn list_args(&self) -> Vec<String>;
+
+    /// Whether `bench_args` runs a single benchmark, otherwise the bench file runs as a
+    /// whole and reports every benchmark in it.
+    fn selects_benchmarks(&self) -> bool {
+        true
+    }
+
+    fn parse_list(&self, output: &str) -> Vec<String> {
+        lazy_static! {
+            static ref RE_BENCH_NAME: Regex = Regex::new(r"(?m)^(.+): bench(mark)?$").unwrap();
+        }
+        RE_BENCH_NAME
+            .captures_iter(output)
+            .map(|c| String::from(&c[1]))
+            .collect()
+    }
+
+    /// Arguments for the bench executable that run exactly one benchmark.
+    fn bench_args(
+        &self,
+        benchmark: &str,
+        measurement_time: u64,
+        warmup_time: u64,
+        sample_size: u64,
+    ) -> Vec<String>;
+
+    /// Seconds one benchmark is expected to run with these settings, `None` when the
+    /// harness decides on that itself.
+    fn estimated_seconds(
+        &self,
+        measurement_time: u64,
+        warmup_time: u64,
+        _sample_size: u64,
+    ) -> Option<f64> {
+        Some((measurement_time + warmup_time) as f64)
+    }
+
+    /// Whether the results are only reported on stdout, which the runner then
+    /// appends to `target/<results dir>/<bench file>.txt`.
+    fn keeps_stdout(&self) -> bool {
+        true
+    }
+
+    fn unit(&self) -> Unit {
+        Unit::Nanoseconds
+    }
+
+    /// Read the results from `data/<timestamp>/<project>/<results dir>`.
+    fn parse_results(&self, dir: &Path) -> Vec<Measurement>;
+}
+
+pub(crate) fn benchmark_id(id: &str) -> BenchmarkId {
+    BenchmarkId {
+        group_id: id.to_string(),
+        function_id: None,
+        value_str: None,
+        throughput: None,
+        full_id: id.to_string(),
+        directory_name: id.to_string(),
+        title: id.to_string(),
+    }
+}
+
+/// Read the stdout files of a harness that reports one value per benchmark per execution.
+/// Every execution becomes one sample of a single iteration.
+pub(crate) fn parse_stdout_files(
+    dir: &Path,
+    parse: impl Fn(&str) -> Vec<(String, f64)>,
+) -> Vec<Measurement> {
+    let mut paths = fs::read_dir(dir)
+        .unwrap_or_else(|err| panic!("Could not read {:?}: {}", dir, err))
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .map_or(false, |extension| extension == "txt")
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut measurements = vec![];
+    for path in paths {
+        let bench_file = path.file_stem().unwrap().to_string_lossy().to_string();
+        let mut values: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+        for (id, value) in parse(&fs::read_to_string(&path).unwrap()) {
+            values.entry(id).or_default().push(value);
+        }
+        for (id, times) in values {
+            measurements.push(Measurement {
+                id: benchmark_id(&id),
+                samples: SampleData {
+                    sampling_mode: "Flat".to_string(),
+                    iters: vec![1.0; times.len()],
+                    times,
+                },
+                estimates: None,
+                bench_file: Some(bench_file.clone()),
+            });
+        }
+    }
+    measurements
+}
+
+#[test]
+fn test_detect() {
+    assert_eq!(
+        HarnessKind::detect(true, "#[bench]\nfn b(b: &mut Bencher) {}"),
+        Some(HarnessKind::Libtest)
+    );
+    assert_eq!(
+        HarnessKind::detect(
+            false,
+            "criterion_group!(benches, b);\ncriterion_main!(benches);"
+        ),
+        Some(HarnessKind::Criterion)
+    );
+    assert_eq!(
+        HarnessKind::detect(false, "fn main() {\n    divan::main();\n}"),
+        Some(HarnessKind::Divan)
+    );
+    assert_eq!(
+        HarnessKind::detect(false, "use iai_callgrind::{main, library_benchmark};"),
+        Some(HarnessKind::IaiCallgrind)
+    );
+    assert_eq!(HarnessKind::detect(false, "fn main() {}"), None);
+}
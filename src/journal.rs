@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cache::ExecutableCache;
+use crate::config::{BenchSettings, Overrides};
+use crate::cpu::CpuSelection;
+use crate::design::Design;
+use crate::execute::{Failure, FailureKind};
+use crate::harness::HarnessKind;
+use crate::manifest::{BenchmarkTiming, Environment};
+
+/// Journals are stored as `journal/<run id>.json`
+pub const JOURNAL_DIR: &str = "journal";
+
+/// One benchmark of one compiled bench file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BenchCommand {
+    /// `project/bench file/benchmark id`
+    pub name: String,
+    pub executable: String,
+    pub benchmark: String,
+    pub workdir: PathBuf,
+    #[serde(default)]
+    pub harness: HarnessKind,
+}
+
+/// The settings a run was started with, a resumed run always uses these.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunSettings {
+    pub iterations: usize,
+    pub measurement_time: u64,
+    pub warmup_time: u64,
+    pub sample_size: u64,
+    pub cpus: CpuSelection,
+    /// Wall-clock seconds after which a benchmark is killed
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    /// How often a benchmark is retried after a failure of one of the `retry_on` kinds
+    #[serde(default)]
+    pub retries: usize,
+    #[serde(default)]
+    pub retry_on: Vec<FailureKind>,
+    /// Projects compiled at the same time, `None` for one per CPU
+    #[serde(default)]
+    pub jobs: Option<usize>,
+    #[serde(default)]
+    pub design: Design,
+    /// Seeds the order of every repetition, so the run can be reproduced
+    #[serde(default)]
+    pub seed: u64,
+    /// Per project and bench file, from the `projects` of `power.toml`
+    #[serde(default)]
+    pub overrides: BTreeMap<String, Overrides>,
+}
+
+impl RunSettings {
+    pub fn compile_jobs(&self) -> usize {
+        self.jobs.unwrap_or_else(|| {
+            thread::available_parallelism().map_or(1, |cpus| cpus.get())
+        })
+    }
+
+    pub fn bench_settings(&self, project: &str, bench_file: &str) -> BenchSettings {
+        BenchSettings::resolve(self, project, bench_file)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IterationProgress {
+    /// Every benchmark of the iteration, in the shuffled order they run in
+    pub order: Vec<BenchCommand>,
+    /// Names of the benchmarks that finished, including the failed ones
+    pub completed: Vec<String>,
+    pub failed: Vec<String>,
+    /// The `data/<timestamp>` directory the results were moved to
+    pub saved: Option<String>,
+    /// Captured every time the benchmarks of the iteration were (re)started
+    #[serde(default)]
+    pub environments: Vec<Environment>,
+    #[serde(default)]
+    pub timings: Vec<BenchmarkTiming>,
+    #[serde(default)]
+    pub failures: Vec<Failure>,
+}
+
+impl IterationProgress {
+    pub fn remaining(&self) -> Vec<BenchCommand> {
+        self.order
+            .iter()
+            .filter(|command| !self.completed.contains(&command.name))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Progress of a `power run`, written after every benchmark so it can be resumed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Journal {
+    pub id: String,
+    pub settings: RunSettings,
+    /// Built by the first iteration, the later ones run the same executables
+    #[serde(default)]
+    pub cache: Option<ExecutableCache>,
+    pub iterations: Vec<IterationProgress>,
+}
+
+impl Journal {
+    pub fn new(settings: RunSettings) -> Journal {
+        Journal {
+            id: chrono::offset::Local::now().timestamp_millis().to_string(),
+            settings,
+            cache: None,
+            iterations: vec![],
+        }
+    }
+
+    pub fn path(id: &str) -> PathBuf {
+        PathBuf::from(JOURNAL_DIR).join(format!("{}.json", id))
+    }
+
+    pub fn store(&self) {
+        let path = Journal::path(&self.id);
+        fs::create_dir_all(JOURNAL_DIR).expect("Could not create journal directory");
+
+        // Write to a temporary file first, so a crash never leaves half a journal
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_string_pretty(self).unwrap())
+            .unwrap_or_else(|err| panic!("Could not write journal {:?}: {}", temporary, err));
+        fs::rename(&temporary, &path)
+            .unwrap_or_else(|err| panic!("Could not write journal {:?}: {}", path, err));
+    }
+
+    pub fn load(id: &str) -> Journal {
+        let path = Journal::path(id);
+        let content = fs::read_to_string(&path)
+            .unwrap_or_else(|err| panic!("Could not read journal {:?}: {}", path, err));
+        serde_json::from_str(&content)
+            .unwrap_or_else(|err| panic!("Could not parse journal {:?}: {}", path, err))
+    }
+}
+
+#[test]
+fn test_remaining() {
+    let command = |name: &str| BenchCommand {
+        name: name.to_string(),
+        executable: "target/release/deps/bench".to_string(),
+        benchmark: name.to_string(),
+        workdir: PathBuf::from("projects/project"),
+        harness: HarnessKind::Criterion,
+    };
+    let progress = IterationProgress {
+        order: vec![command("c"), command("a"), command("b")],
+        completed: vec!["c".to_string(), "b".to_string()],
+        failed: vec!["b".to_string()],
+        saved: None,
+        ..Default::default()
+    };
+    assert_eq!(progress.remaining(), vec![command("a")]);
+
+    let serialized = serde_json::to_string(&progress).unwrap();
+    let deserialized: IterationProgress = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(deserialized.order, progress.order);
+}
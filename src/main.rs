@@ -1,17 +1,32 @@
 use std::env;
+use std::path::PathBuf;
 use std::process::Command;
 
 use caps::{CapSet, Capability, CapsHashSet};
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser};
 use crate::coverage::{gather_coverage, gather_instructions};
 
 use crate::data::project::{
     cargo_check_all_projects, clone_projects_from_targets, find_all_benchmarks,
+    print_listing_summary,
 };
 
+mod cache;
 mod collect;
+mod config;
 mod coverage;
+mod cpu;
+mod design;
 mod data;
+mod execute;
+mod harness;
+mod journal;
+mod manifest;
+mod plan;
+mod snapshot;
+mod stats;
+mod store;
 
 #[derive(Parser, Debug)]
 #[command(name = "power")]
@@ -23,8 +38,12 @@ enum Cli {
     Project(ProjectCommand),
     #[command(subcommand, name = "stat")]
     Statistics(StatisticsCommand),
+    #[command(subcommand)]
+    Store(StoreCommand),
     #[command(about = "Run the necessary commands to set up the environment. Needs root.")]
     Prep,
+    #[command(about = "Restore the CPU and kernel settings from before the last `power run` and `power prep`. Needs root.")]
+    Restore,
     #[command(about = "Run `cargo check --benches` on all projects")]
     Check,
     #[command(about= "Collect coverage data from all projects.")]
@@ -48,49 +67,507 @@ struct ExperimentSettings {
     #[arg(short, long, default_value = "300")]
     sample_size: u64,
 
+    /// Shorthand for `--design consecutive`
+    #[arg(long, conflicts_with = "design")]
+    no_rmit: bool,
+
+    /// Order of the benchmarks: rmit, blocked, latin-square or consecutive
+    #[arg(long, default_value = "rmit")]
+    design: design::Design,
+
+    /// Seed for the order of the benchmarks, random if not given
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Continue an interrupted run from its journal, with the settings it was started with
+    #[arg(long, conflicts_with = "no_rmit")]
+    resume: Option<String>,
+
+    /// CPUs that run the benchmarks, e.g. `3` or `2-3`. Their SMT siblings are taken offline.
+    #[arg(long, default_value = "3")]
+    bench_cpus: cpu::CpuList,
+
+    /// CPUs that stay online for everything else
+    #[arg(long, default_value = "0")]
+    housekeeping_cpus: cpu::CpuList,
+
+    /// Kill a benchmark, and everything it started, after this many seconds
     #[arg(long)]
-    no_rmit: bool
+    timeout: Option<u64>,
+
+    /// Retry a benchmark this many times after a failure of a `--retry-on` kind
+    #[arg(long, default_value = "0")]
+    retries: usize,
+
+    /// Failure kinds worth retrying: panic, timeout, signal or error
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "timeout,signal",
+        value_parser = execute::FailureKind::parse_retryable
+    )]
+    retry_on: Vec<execute::FailureKind>,
+
+    /// Projects to compile at the same time, defaults to the number of CPUs
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    /// Print the schedule and its estimated duration without compiling or running anything
+    #[arg(long, conflicts_with = "resume")]
+    plan: bool,
+
+    /// Settings and per-project overrides, flags given on the command line take precedence
+    #[arg(long, num_args = 0..=1, default_missing_value = config::CONFIG_PATH, conflicts_with = "resume")]
+    config: Option<PathBuf>,
 }
 
 #[derive(clap::Subcommand, Debug)]
 enum ProjectCommand {
     Parse,
     Download,
+    #[command(about = "List the projects in the registry with the commit their benchmarks were discovered at")]
+    List,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum StoreCommand {
+    #[command(about = "Import the data, coverage and instruction results of earlier experiments")]
+    Import(ImportSettings),
+}
+
+#[derive(clap::Args, Debug)]
+struct ImportSettings {
+    #[arg(long, default_value = store::DEFAULT_PATH)]
+    db: PathBuf,
+
+    #[arg(short, long, default_value = "data")]
+    data: PathBuf,
+
+    #[arg(short, long, default_value = "coverage")]
+    coverage: PathBuf,
+
+    #[arg(short, long, default_value = "instructions.csv")]
+    instructions: PathBuf,
 }
 
 #[derive(clap::Subcommand, Debug)]
 enum StatisticsCommand {
-    Parse,
-    Merge,
+    #[command(about = "Collect all benchmark samples in the data directory into one table")]
+    Parse(ParseSettings),
+    #[command(about = "Merge RMIT iterations into one dataset and report differences between them")]
+    Merge(MergeSettings),
+    #[command(about = "Compute stability metrics per benchmark from a merged dataset")]
+    Metrics(MetricsSettings),
+    #[command(about = "Detect regressions and improvements per benchmark between two result sets")]
+    Compare(CompareSettings),
+    #[command(about = "Estimate the repetitions and sample size needed to reach a target RCIW")]
+    Repetitions(RepetitionSettings),
+    #[command(about = "Detect multimodal distributions and outliers per benchmark")]
+    Diagnostics(DiagnosticSettings),
+    #[command(about = "Correlate language feature counts from `power coverage` with stability metrics")]
+    Correlation(CorrelationSettings),
+    #[command(about = "Detect drift, step changes and periodicity across RMIT iterations")]
+    Drift(DriftSettings),
+}
+
+#[derive(clap::Args, Debug)]
+struct ParseSettings {
+    #[arg(short, long, default_value = "data")]
+    data: PathBuf,
+
+    #[arg(short, long, default_value = "samples.csv")]
+    output: PathBuf,
+
+    #[arg(short, long, default_value = "estimates.csv")]
+    estimates: PathBuf,
+
+    /// Also store every run in this database
+    #[arg(long, default_value = store::DEFAULT_PATH)]
+    db: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct MergeSettings {
+    /// Runs to merge, as paths or timestamps in the data directory. Defaults to all runs.
+    runs: Vec<PathBuf>,
+
+    #[arg(short, long, default_value = "data")]
+    data: PathBuf,
+
+    #[arg(short, long, default_value = "merged.csv")]
+    output: PathBuf,
+
+    #[arg(short, long, default_value = "merge_report.csv")]
+    report: PathBuf,
+
+    /// Only keep benchmarks that are present in every iteration
+    #[arg(long)]
+    intersect: bool,
+
+    /// Also store the results in this database
+    #[arg(long, default_value = store::DEFAULT_PATH)]
+    db: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct MetricsSettings {
+    #[arg(short, long, default_value = "merged.csv")]
+    input: PathBuf,
+
+    #[arg(short, long, default_value = "statistics.csv")]
+    output: PathBuf,
+
+    #[arg(short, long, default_value = "0.99")]
+    confidence_level: f64,
+
+    /// Statistic to bootstrap: `mean`, `median` or a quantile such as `0.9`
+    #[arg(short, long, default_value = "mean")]
+    estimator: stats::bootstrap::Estimator,
+
+    #[arg(long, default_value = "0")]
+    seed: u64,
+
+    #[arg(long, default_value = "10000")]
+    resamples: usize,
+
+    /// Also store the statistics in this database
+    #[arg(long, default_value = store::DEFAULT_PATH)]
+    db: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct RepetitionSettings {
+    #[arg(short, long, default_value = "merged.csv")]
+    input: PathBuf,
+
+    #[arg(short, long, default_value = "repetitions.csv")]
+    output: PathBuf,
+
+    /// Expected RCIW per number of repetitions and sample size
+    #[arg(long, default_value = "convergence.csv")]
+    curve: PathBuf,
+
+    /// Target RCIW, e.g. 0.01 for 1%
+    #[arg(short, long, value_delimiter = ',', default_value = "0.01,0.03")]
+    target: Vec<f64>,
+
+    #[arg(short, long, value_delimiter = ',', default_value = "10,25,50,100,200,300")]
+    sample_sizes: Vec<usize>,
+
+    /// Random subsets of iterations drawn per number of repetitions
+    #[arg(long, default_value = "20")]
+    subsamples: usize,
+
+    #[arg(short, long, default_value = "0.99")]
+    confidence_level: f64,
+
+    /// Statistic of the samples within one iteration: `mean`, `median` or a quantile
+    #[arg(short, long, default_value = "median")]
+    estimator: stats::bootstrap::Estimator,
+
+    #[arg(long, default_value = "0")]
+    seed: u64,
+
+    /// Also store the results in this database
+    #[arg(long, default_value = store::DEFAULT_PATH)]
+    db: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct DiagnosticSettings {
+    #[arg(short, long, default_value = "merged.csv")]
+    input: PathBuf,
+
+    #[arg(short, long, default_value = "diagnostics.csv")]
+    output: PathBuf,
+
+    /// The mode the median of each RMIT iteration fell into
+    #[arg(long, default_value = "iteration_modes.csv")]
+    modes: PathBuf,
+
+    /// Significance level of the dip test
+    #[arg(short, long, default_value = "0.05")]
+    alpha: f64,
+
+    /// Uniform samples simulated for the p-value of the dip test
+    #[arg(long, default_value = "200")]
+    simulations: usize,
+
+    /// Ignore density peaks lower than this fraction of the highest peak
+    #[arg(long, default_value = "0.05")]
+    min_peak: f64,
+
+    #[arg(long, default_value = "0")]
+    seed: u64,
+
+    /// Also store the results in this database
+    #[arg(long, default_value = store::DEFAULT_PATH)]
+    db: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct CorrelationSettings {
+    /// Statistics computed by `power stat metrics`
+    #[arg(short, long, default_value = "statistics.csv")]
+    input: PathBuf,
+
+    #[arg(long, default_value = "coverage")]
+    coverage: PathBuf,
+
+    #[arg(short, long, default_value = "correlations.csv")]
+    output: PathBuf,
+
+    /// Stability measures and feature counts per benchmark, for modelling elsewhere
+    #[arg(long, default_value = "feature_matrix.csv")]
+    matrix: PathBuf,
+
+    #[arg(short, long, default_value = "sample")]
+    metric: stats::metrics::Metric,
+
+    /// Multiple comparison correction: `none`, `bonferroni`, `holm` or `bh`
+    #[arg(long, default_value = "bh")]
+    correction: stats::hypothesis::Correction,
+
+    /// Also store the results in this database
+    #[arg(long, default_value = store::DEFAULT_PATH)]
+    db: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct DriftSettings {
+    #[arg(short, long, default_value = "merged.csv")]
+    input: PathBuf,
+
+    #[arg(short, long, default_value = "drift.csv")]
+    output: PathBuf,
+
+    /// Deviating iterations per hour of wall-clock time
+    #[arg(long, default_value = "hours.csv")]
+    hours: PathBuf,
+
+    #[arg(short, long, default_value = "0.05")]
+    alpha: f64,
+
+    /// Multiple comparison correction: `none`, `bonferroni`, `holm` or `bh`
+    #[arg(long, default_value = "bh")]
+    correction: stats::hypothesis::Correction,
+
+    /// Relative deviation from the benchmark median for an iteration to count as affected
+    #[arg(short, long, default_value = "0.02")]
+    threshold: f64,
+
+    /// Also store the results in this database
+    #[arg(long, default_value = store::DEFAULT_PATH)]
+    db: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct CompareSettings {
+    /// A merged dataset (.csv), or a run directory or timestamp in the data directory
+    baseline: PathBuf,
+
+    /// A merged dataset (.csv), or a run directory or timestamp in the data directory
+    candidate: PathBuf,
+
+    #[arg(short, long, default_value = "data")]
+    data: PathBuf,
+
+    #[arg(short, long, default_value = "comparison.csv")]
+    output: PathBuf,
+
+    #[arg(short, long, default_value = "sample")]
+    metric: stats::metrics::Metric,
+
+    #[arg(short, long, default_value = "0.99")]
+    confidence_level: f64,
+
+    /// Multiple comparison correction: `none`, `bonferroni`, `holm` or `bh`
+    #[arg(long, default_value = "bh")]
+    correction: stats::hypothesis::Correction,
+
+    /// Minimal relative change of the median to count as a regression or improvement
+    #[arg(short, long, default_value = "0.01")]
+    threshold: f64,
+
+    #[arg(long, default_value = "0")]
+    seed: u64,
+
+    #[arg(long, default_value = "10000")]
+    resamples: usize,
+
+    /// Also store the results in this database
+    #[arg(long, default_value = store::DEFAULT_PATH)]
+    db: PathBuf,
 }
 
 fn main() {
-    let parse = Cli::parse();
+    // Kept to tell the flags given on the command line apart from the defaults
+    let matches = Cli::command().get_matches();
+    let parse = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
     match parse {
         Cli::Experiment(settings) => {
+            let mut run_settings = journal::RunSettings {
+                iterations: settings.repetitions,
+                measurement_time: settings.measurement_time,
+                warmup_time: settings.warmup_time,
+                sample_size: settings.sample_size,
+                cpus: cpu::CpuSelection {
+                    bench: settings.bench_cpus,
+                    housekeeping: settings.housekeeping_cpus,
+                },
+                timeout: settings.timeout,
+                retries: settings.retries,
+                retry_on: settings.retry_on,
+                jobs: settings.jobs,
+                design: if settings.no_rmit {
+                    design::Design::Consecutive
+                } else {
+                    settings.design
+                },
+                seed: settings.seed.unwrap_or_else(rand::random),
+                overrides: Default::default(),
+            };
+            if let Some(path) = &settings.config {
+                let run_matches = matches.subcommand_matches("run").unwrap();
+                config::Config::load(path).apply(&mut run_settings, |argument| {
+                    run_matches.value_source(argument) == Some(ValueSource::CommandLine)
+                        || (argument == "design" && settings.no_rmit)
+                });
+            }
+            if settings.plan {
+                plan::Plan::new(run_settings).print();
+                return;
+            }
             check_capabilities();
-            collect::run(
-                settings.repetitions,
-                settings.measurement_time,
-                settings.warmup_time,
-                settings.sample_size,
-                settings.no_rmit
-            )
+            collect::run(run_settings, settings.resume)
         }
         Cli::Project(subcommand) => match subcommand {
             ProjectCommand::Parse => {
-                find_all_benchmarks()
-                    .iter()
-                    .for_each(|project| project.store().expect("Could not store project"));
+                let mut store = store::Store::open_default();
+                let registry = data::registry::Registry::open_default();
+                let projects = find_all_benchmarks();
+                projects.iter().for_each(|project| {
+                    registry.store(project);
+                    store.insert_project(project);
+                });
+                print_listing_summary(&projects);
             }
             ProjectCommand::Download => {
                 println!("Cloning projects that were found in targets.csv");
                 clone_projects_from_targets();
             }
+            ProjectCommand::List => data::registry::Registry::open_default().print(),
         },
         Cli::Statistics(subcommand) => match subcommand {
-            StatisticsCommand::Parse => {}
-            StatisticsCommand::Merge => {}
+            StatisticsCommand::Parse(settings) => {
+                stats::parse::parse(&settings.data, &settings.output, &settings.estimates);
+                let mut store = store::Store::open(&settings.db);
+                for run in stats::find_runs(&settings.data) {
+                    store.insert_run(&run);
+                }
+            }
+            StatisticsCommand::Merge(settings) => {
+                stats::merge::merge(
+                    &settings.data,
+                    &settings.runs,
+                    settings.intersect,
+                    &settings.output,
+                    &settings.report,
+                    Some(&settings.db),
+                );
+            }
+            StatisticsCommand::Metrics(settings) => {
+                stats::metrics::metrics(
+                    &settings.input,
+                    &settings.output,
+                    settings.confidence_level,
+                    settings.estimator,
+                    settings.seed,
+                    settings.resamples,
+                    Some(&settings.db),
+                );
+            }
+            StatisticsCommand::Compare(settings) => {
+                stats::compare::compare(
+                    &settings.data,
+                    &settings.baseline,
+                    &settings.candidate,
+                    &settings.output,
+                    &stats::compare::CompareOptions {
+                        metric: settings.metric,
+                        confidence_level: settings.confidence_level,
+                        correction: settings.correction,
+                        threshold: settings.threshold,
+                        seed: settings.seed,
+                        resamples: settings.resamples,
+                    },
+                    Some(&settings.db),
+                );
+            }
+            StatisticsCommand::Repetitions(settings) => {
+                stats::repetitions::repetitions(
+                    &settings.input,
+                    &settings.curve,
+                    &settings.output,
+                    &stats::repetitions::RepetitionOptions {
+                        targets: settings.target,
+                        sample_sizes: settings.sample_sizes,
+                        subsamples: settings.subsamples,
+                        confidence_level: settings.confidence_level,
+                        estimator: settings.estimator,
+                        seed: settings.seed,
+                    },
+                    Some(&settings.db),
+                );
+            }
+            StatisticsCommand::Diagnostics(settings) => {
+                stats::diagnostics::diagnostics(
+                    &settings.input,
+                    &settings.output,
+                    &settings.modes,
+                    &stats::diagnostics::DiagnosticOptions {
+                        alpha: settings.alpha,
+                        simulations: settings.simulations,
+                        min_peak: settings.min_peak,
+                        grid: 512,
+                        seed: settings.seed,
+                    },
+                    Some(&settings.db),
+                );
+            }
+            StatisticsCommand::Correlation(settings) => {
+                stats::correlation::correlation(
+                    &settings.input,
+                    &settings.coverage,
+                    settings.metric,
+                    settings.correction,
+                    &settings.output,
+                    &settings.matrix,
+                    Some(&settings.db),
+                );
+            }
+            StatisticsCommand::Drift(settings) => {
+                stats::drift::drift_analysis(
+                    &settings.input,
+                    &settings.output,
+                    &settings.hours,
+                    &stats::drift::DriftOptions {
+                        alpha: settings.alpha,
+                        correction: settings.correction,
+                        threshold: settings.threshold,
+                    },
+                    Some(&settings.db),
+                );
+            }
+        },
+        Cli::Store(subcommand) => match subcommand {
+            StoreCommand::Import(settings) => {
+                store::Store::open(&settings.db).import(
+                    &settings.data,
+                    &settings.coverage,
+                    &settings.instructions,
+                );
+            }
         },
         Cli::Prep => {
             println!("{:?}", CapSet::Effective);
@@ -113,7 +590,12 @@ fn main() {
                 }
                 Err(err) => panic!("Error setting own capabilities: {}", err),
             }
+            snapshot::lower_perf_event_paranoid();
         }
+        Cli::Restore => match snapshot::Snapshot::load() {
+            Some(mut snapshot) => snapshot.restore(),
+            None => println!("Found no {}, nothing to restore", snapshot::SNAPSHOT_PATH),
+        },
         Cli::Check => {
             cargo_check_all_projects();
         },
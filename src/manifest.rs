@@ -0,0 +1,146 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, CONFIG_PATH};
+use crate::cpu;
+use crate::data::project::get_workdir_for_project;
+use crate::execute::Failure;
+use crate::journal::RunSettings;
+use crate::snapshot::{cpusets, CpuState, CPUSET};
+
+/// Written to every `data/<timestamp>` directory
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// The state of the machine while benchmarking, `None` where it could not be determined.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Environment {
+    pub captured: String,
+    pub kernel: Option<String>,
+    pub cpu_model: Option<String>,
+    pub microcode: Option<String>,
+    pub online: Option<String>,
+    pub cpus: Vec<CpuState>,
+    /// CPUs of the benchmark cpuset
+    pub cpuset: Option<String>,
+    pub rustc: Option<String>,
+    pub cargo: Option<String>,
+}
+
+/// When one benchmark of the iteration ran, with all its repetitions for `--no-rmit`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkTiming {
+    pub name: String,
+    pub started: String,
+    pub finished: String,
+    pub success: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectRevision {
+    pub project: String,
+    pub commit: Option<String>,
+    /// Uncommitted changes in the checkout
+    pub dirty: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub run: String,
+    /// Journal and iteration the run belongs to, `None` for `--no-rmit`
+    pub journal: Option<String>,
+    pub iteration: Option<usize>,
+    pub rmit: bool,
+    pub settings: RunSettings,
+    /// Captured every time the benchmarks of the run were (re)started
+    pub environments: Vec<Environment>,
+    pub projects: Vec<ProjectRevision>,
+    /// Benchmark names in the order they were scheduled
+    pub order: Vec<String>,
+    pub benchmarks: Vec<BenchmarkTiming>,
+    /// Every failed attempt, including compile errors and Criterion warnings
+    pub failures: Vec<Failure>,
+}
+
+pub fn now() -> String {
+    chrono::offset::Local::now().to_rfc3339()
+}
+
+/// Trimmed stdout of a successful command.
+fn command_output(command: &mut Command) -> Option<String> {
+    let output = command.output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The value of the first `key : value` line in /proc/cpuinfo.
+fn cpuinfo(cpuinfo: &str, key: &str) -> Option<String> {
+    cpuinfo.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        (name.trim() == key).then(|| value.trim().to_string())
+    })
+}
+
+impl Environment {
+    pub fn capture() -> Environment {
+        let root = Path::new(cpu::SYSFS_CPU);
+        let proc_cpuinfo = fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+        Environment {
+            captured: now(),
+            kernel: command_output(Command::new("uname").arg("-r")),
+            cpu_model: cpuinfo(&proc_cpuinfo, "model name"),
+            microcode: cpuinfo(&proc_cpuinfo, "microcode"),
+            online: fs::read_to_string(root.join("online"))
+                .ok()
+                .map(|online| online.trim().to_string()),
+            cpus: CpuState::capture(root),
+            cpuset: cpusets().and_then(|sets| {
+                sets.into_iter()
+                    .find(|(name, _)| name == CPUSET)
+                    .map(|(_, cpus)| cpus)
+            }),
+            rustc: command_output(Command::new("rustc").arg("--version")),
+            cargo: command_output(Command::new("cargo").arg("--version")),
+        }
+    }
+}
+
+impl ProjectRevision {
+    pub fn capture(project: &str) -> ProjectRevision {
+        let workdir = get_workdir_for_project(project);
+        let git =
+            |args: &[&str]| command_output(Command::new("git").arg("-C").arg(&workdir).args(args));
+        ProjectRevision {
+            project: project.to_string(),
+            commit: git(&["rev-parse", "HEAD"]),
+            dirty: git(&["status", "--porcelain"]).map(|status| !status.is_empty()),
+        }
+    }
+}
+
+impl Manifest {
+    /// Write the manifest and the resolved config into the `data/<timestamp>` directory of the run.
+    pub fn write(&self, run_dir: &Path) {
+        fs::create_dir_all(run_dir)
+            .unwrap_or_else(|err| panic!("Could not create {:?}: {}", run_dir, err));
+        let path = run_dir.join(MANIFEST_FILE);
+        fs::write(&path, serde_json::to_string_pretty(self).unwrap())
+            .unwrap_or_else(|err| panic!("Could not write manifest {:?}: {}", path, err));
+        Config::resolved(&self.settings).write(&run_dir.join(CONFIG_PATH));
+    }
+}
+
+#[test]
+fn test_cpuinfo() {
+    let content = "processor\t: 0\nvendor_id\t: GenuineIntel\nmodel name\t: Intel(R) Core(TM) i7-8700 CPU @ 3.20GHz\nmicrocode\t: 0xf4\n\nprocessor\t: 1\nmodel name\t: other\n";
+    assert_eq!(
+        cpuinfo(content, "model name").as_deref(),
+        Some("Intel(R) Core(TM) i7-8700 CPU @ 3.20GHz")
+    );
+    assert_eq!(cpuinfo(content, "microcode").as_deref(), Some("0xf4"));
+    assert_eq!(cpuinfo(content, "flags"), None);
+}
@@ -0,0 +1,347 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::time::Duration;
+
+use indicatif::HumanDuration;
+use serde::{Deserialize, Serialize};
+
+use crate::data::project::get_workdir_for_project;
+use crate::data::registry::Registry;
+use crate::design::Design;
+use crate::harness::HarnessKind;
+use crate::journal::RunSettings;
+
+/// Seconds the last compilation of every bench file took, by `project/bench file`
+pub const COMPILE_TIMES_PATH: &str = "compile-times.json";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CompileTimes(pub BTreeMap<String, f64>);
+
+impl CompileTimes {
+    pub fn load() -> CompileTimes {
+        fs::read_to_string(COMPILE_TIMES_PATH)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// Keep the newest observations, bench files that were not compiled keep their old time.
+    pub fn record(observed: Vec<(String, f64)>) {
+        let mut times = CompileTimes::load();
+        times.0.extend(observed);
+        fs::write(
+            COMPILE_TIMES_PATH,
+            serde_json::to_string_pretty(&times).unwrap(),
+        )
+        .unwrap_or_else(|err| panic!("Could not write {}: {}", COMPILE_TIMES_PATH, err));
+    }
+
+    fn mean(&self) -> Option<f64> {
+        if self.0.is_empty() {
+            None
+        } else {
+            Some(self.0.values().sum::<f64>() / self.0.len() as f64)
+        }
+    }
+}
+
+/// A bench file that will be compiled and whose benchmarks will run.
+#[derive(Debug, Clone)]
+pub struct PlannedBenchFile {
+    pub project: String,
+    pub name: String,
+    pub harness: HarnessKind,
+    pub benchmarks: Vec<String>,
+    /// Executions per repetition, one when the bench file runs as a whole
+    pub executions: usize,
+    /// Observed earlier, `None` if the bench file was never compiled
+    pub compile_seconds: Option<f64>,
+}
+
+/// What a `power run` would do, without compiling or running anything.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub settings: RunSettings,
+    pub bench_files: Vec<PlannedBenchFile>,
+    /// `(project or project/bench file, reason)`
+    pub skipped: Vec<(String, String)>,
+    /// Used for bench files that were never compiled
+    pub mean_compile_seconds: Option<f64>,
+}
+
+/// Length of `jobs` parallel queues that each take the longest remaining task.
+fn schedule(mut tasks: Vec<f64>, jobs: usize) -> f64 {
+    tasks.sort_by(|a, b| b.total_cmp(a));
+    let mut queues = vec![0.0_f64; jobs.max(1)];
+    for task in tasks {
+        let shortest = queues.iter_mut().min_by(|a, b| a.total_cmp(b)).unwrap();
+        *shortest += task;
+    }
+    queues.into_iter().fold(0.0, f64::max)
+}
+
+fn human(seconds: f64) -> HumanDuration {
+    HumanDuration(Duration::from_secs_f64(seconds))
+}
+
+impl Plan {
+    /// Plan the bench files of the projects in `targets.csv`.
+    pub fn new(settings: RunSettings) -> Plan {
+        let times = CompileTimes::load();
+        let mut plan = Plan {
+            settings,
+            bench_files: vec![],
+            skipped: vec![],
+            mean_compile_seconds: times.mean(),
+        };
+
+        for (name, project) in Registry::open_default().load_targets() {
+            let project = match project {
+                Ok(project) => project,
+                Err(err) => {
+                    plan.skipped.push((name, err.to_string()));
+                    continue;
+                }
+            };
+            if !get_workdir_for_project(&name).exists() {
+                plan.skipped.push((
+                    name,
+                    "not downloaded, run `power project download`".to_string(),
+                ));
+                continue;
+            }
+
+            for failure in &project.listing_failures {
+                plan.skipped.push((
+                    format!("{}/{}", project.name, failure.bench_file),
+                    format!("benchmarks could not be listed, {}", failure.kind),
+                ));
+            }
+            for bench_file in project.bench_files {
+                let name = format!("{}/{}", project.name, bench_file.name);
+                if bench_file.benches.is_empty() {
+                    plan.skipped.push((name, "no benchmarks".to_string()));
+                    continue;
+                }
+                if plan
+                    .settings
+                    .bench_settings(&project.name, &bench_file.name)
+                    .exclude
+                {
+                    plan.skipped
+                        .push((name, "excluded in the config".to_string()));
+                    continue;
+                }
+                plan.bench_files.push(PlannedBenchFile {
+                    compile_seconds: times.0.get(&name).copied(),
+                    executions: bench_file.runs().len(),
+                    project: project.name.clone(),
+                    name: bench_file.name,
+                    harness: bench_file.harness,
+                    benchmarks: bench_file.benches,
+                });
+            }
+        }
+        plan
+    }
+
+    /// Seconds one execution takes, limited by the timeout.
+    pub fn benchmark_seconds(&self, bench_file: &PlannedBenchFile) -> Option<f64> {
+        let settings = self
+            .settings
+            .bench_settings(&bench_file.project, &bench_file.name);
+        let seconds = bench_file.harness.adapter().estimated_seconds(
+            settings.measurement_time,
+            settings.warmup_time,
+            settings.sample_size,
+        );
+        match self.settings.timeout {
+            Some(timeout) => Some(seconds.unwrap_or(timeout as f64).min(timeout as f64)),
+            None => seconds,
+        }
+    }
+
+    /// Seconds to compile every bench file once, projects run in parallel on the compile jobs.
+    pub fn compile_seconds(&self) -> Option<f64> {
+        let mut projects: BTreeMap<&str, f64> = BTreeMap::new();
+        for bench_file in &self.bench_files {
+            let seconds = bench_file.compile_seconds.or(self.mean_compile_seconds)?;
+            *projects.entry(&bench_file.project).or_default() += seconds;
+        }
+        Some(schedule(
+            projects.into_values().collect(),
+            self.settings.compile_jobs(),
+        ))
+    }
+
+    /// Seconds to run everything once in every repetition, and the number of executions
+    /// without an estimate.
+    pub fn run_seconds(&self) -> (f64, usize) {
+        let mut seconds = 0.0;
+        let mut unknown = 0;
+        for bench_file in &self.bench_files {
+            match self.benchmark_seconds(bench_file) {
+                Some(execution) => seconds += execution * bench_file.executions as f64,
+                None => unknown += bench_file.executions,
+            }
+        }
+        (seconds * self.settings.iterations as f64, unknown)
+    }
+
+    pub fn print(&self) {
+        let settings = &self.settings;
+        let order = match settings.design {
+            Design::Rmit => "every repetition runs all benchmarks in a new random order",
+            Design::Blocked => "every repetition runs the projects in a random order, with the benchmarks of a project in a random order one after the other",
+            Design::LatinSquare => "every benchmark is followed by every other benchmark equally often",
+            Design::Consecutive => "every benchmark runs all its repetitions in a row",
+        };
+        println!(
+            "{} repetitions with the {} design and seed {}: {}",
+            settings.iterations, settings.design, settings.seed, order
+        );
+        println!(
+            "Measurement {}s, warm-up {}s, {} samples, timeout {}, {} retries, {} compile jobs\n",
+            settings.measurement_time,
+            settings.warmup_time,
+            settings.sample_size,
+            settings
+                .timeout
+                .map_or("none".to_string(), |timeout| format!("{}s", timeout)),
+            settings.retries,
+            settings.compile_jobs()
+        );
+
+        let mut project = "";
+        for bench_file in &self.bench_files {
+            if bench_file.project != project {
+                project = &bench_file.project;
+                println!("{}", project);
+            }
+            let compile = bench_file
+                .compile_seconds
+                .map_or("never compiled".to_string(), |seconds| {
+                    format!("compiles in {}", human(seconds))
+                });
+            let benchmark = self
+                .benchmark_seconds(bench_file)
+                .map_or("unknown".to_string(), |seconds| {
+                    format!("{}", human(seconds))
+                });
+            println!(
+                "  {} ({:?}, {}, {} per benchmark)",
+                bench_file.name, bench_file.harness, compile, benchmark
+            );
+            for id in &bench_file.benchmarks {
+                println!("    {}", id);
+            }
+        }
+
+        if !self.skipped.is_empty() {
+            println!("\nSkipped:");
+            for (name, reason) in &self.skipped {
+                println!("  {}: {}", name, reason);
+            }
+        }
+
+        let benchmarks = self
+            .bench_files
+            .iter()
+            .map(|bench_file| bench_file.benchmarks.len())
+            .sum::<usize>();
+        let executions = self
+            .bench_files
+            .iter()
+            .map(|bench_file| bench_file.executions)
+            .sum::<usize>();
+        let compile = self.compile_seconds();
+        let (run, unknown) = self.run_seconds();
+        println!(
+            "\n{} projects, {} bench files, {} benchmarks, {} executions",
+            self.bench_files
+                .iter()
+                .map(|bench_file| &bench_file.project)
+                .collect::<BTreeSet<_>>()
+                .len(),
+            self.bench_files.len(),
+            benchmarks,
+            executions * settings.iterations
+        );
+        match compile {
+            Some(compile) => println!("Compiling: {}", human(compile)),
+            None => println!("Compiling: unknown, no compile times were observed yet"),
+        }
+        println!("Benchmarks: {}", human(run));
+        if unknown > 0 {
+            println!("  not counting {} executions without an estimate", unknown);
+        }
+        println!("Total: {}", human(compile.unwrap_or_default() + run));
+
+        let period = settings.design.period(executions);
+        if settings.iterations % period != 0 {
+            println!(
+                "Warning: the {} design is only balanced for a multiple of {} repetitions",
+                settings.design, period
+            );
+        }
+    }
+}
+
+#[test]
+fn test_plan_estimate() {
+    let bench_file =
+        |project: &str, harness, benchmarks: usize, compile_seconds| PlannedBenchFile {
+            project: project.to_string(),
+            name: "bench".to_string(),
+            harness,
+            benchmarks: (0..benchmarks).map(|i| i.to_string()).collect(),
+            executions: if harness == HarnessKind::IaiCallgrind {
+                1
+            } else {
+                benchmarks
+            },
+            compile_seconds,
+        };
+    let mut plan = Plan {
+        settings: RunSettings {
+            iterations: 2,
+            measurement_time: 30,
+            warmup_time: 5,
+            sample_size: 300,
+            cpus: crate::cpu::CpuSelection {
+                bench: crate::cpu::CpuList(vec![3]),
+                housekeeping: crate::cpu::CpuList(vec![0]),
+            },
+            timeout: None,
+            retries: 0,
+            retry_on: vec![],
+            jobs: Some(2),
+            design: Design::Rmit,
+            seed: 0,
+            overrides: Default::default(),
+        },
+        bench_files: vec![
+            bench_file("a", HarnessKind::Criterion, 3, Some(60.0)),
+            bench_file("a", HarnessKind::Libtest, 2, Some(40.0)),
+            bench_file("b", HarnessKind::Divan, 1, None),
+            bench_file("c", HarnessKind::IaiCallgrind, 3, Some(30.0)),
+        ],
+        skipped: vec![],
+        mean_compile_seconds: Some(50.0),
+    };
+
+    // a takes 100s on one job, b and c share the other
+    assert_eq!(plan.compile_seconds(), Some(100.0));
+    assert_eq!(
+        plan.run_seconds(),
+        (2.0 * (3.0 * 35.0 + 2.0 * 3.0 + 30.0), 1)
+    );
+
+    plan.settings.timeout = Some(10);
+    assert_eq!(
+        plan.run_seconds(),
+        (2.0 * (3.0 * 10.0 + 2.0 * 3.0 + 10.0 + 10.0), 0)
+    );
+    plan.mean_compile_seconds = None;
+    assert_eq!(plan.compile_seconds(), None);
+}
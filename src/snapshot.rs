@@ -0,0 +1,375 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::{Handle, Signals};
+
+use crate::cpu;
+use crate::execute;
+
+/// The settings from before the last `power run` and `power prep`, reapplied by `power restore`
+pub const SNAPSHOT_PATH: &str = "snapshot.json";
+const PERF_EVENT_PARANOID: &str = "/proc/sys/kernel/perf_event_paranoid";
+/// The cpuset `power run` creates for the benchmark CPUs
+pub const CPUSET: &str = "BENCH";
+
+/// The settings of one CPU, `None` when the file does not exist, e.g. cpufreq of an offline CPU.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CpuState {
+    pub cpu: usize,
+    pub online: Option<bool>,
+    pub governor: Option<String>,
+    pub min_freq: Option<String>,
+    pub max_freq: Option<String>,
+}
+
+/// Everything `power run` changes while isolating the benchmark CPUs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CpuSnapshot {
+    pub taken: String,
+    pub cpus: Vec<CpuState>,
+    /// Whether the cpuset existed before the run, otherwise it is destroyed on restore
+    pub bench_cpuset: bool,
+    /// The CPUs of the cpuset if it existed, `cset set -c` changes them
+    #[serde(default)]
+    pub bench_cpuset_cpus: Option<String>,
+    pub restored: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Taken by `power run`
+    pub cpus: Option<CpuSnapshot>,
+    /// Taken by `power prep` before lowering it
+    pub perf_event_paranoid: Option<String>,
+}
+
+fn read_setting(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|content| content.trim().to_string())
+}
+
+/// Restoring continues after a failed write, so as much as possible is restored.
+fn write_setting(path: &Path, value: &str) {
+    if read_setting(path).as_deref() == Some(value) {
+        return;
+    }
+    match fs::write(path, value) {
+        Ok(_) => println!("Restored {:?} to {}", path, value),
+        Err(err) => println!("Failed to restore {:?} to {}: {}", path, value, err),
+    }
+}
+
+/// `(name, cpus)` of every cpuset, `None` if `cset` could not list them.
+pub fn cpusets() -> Option<Vec<(String, String)>> {
+    let output = Command::new("cset").args(["set", "-l"]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // Rows look like `BENCH  3 n  0 n  0  0 /BENCH`, the header does not end with a path
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+            .filter(|columns| columns.len() > 2 && columns.last().unwrap().starts_with('/'))
+            .map(|columns| (columns[0].to_string(), columns[1].to_string()))
+            .collect(),
+    )
+}
+
+/// The CPUs of the cpuset if it exists, `None` if `cset` could not list the cpusets.
+fn cpuset_cpus() -> Option<Option<String>> {
+    Some(
+        cpusets()?
+            .into_iter()
+            .find(|(name, _)| name == CPUSET)
+            .map(|(_, cpus)| cpus),
+    )
+}
+
+/// Whether the cpuset exists, `None` if `cset` could not list the cpusets.
+fn cpuset_exists() -> Option<bool> {
+    cpuset_cpus().map(|cpus| cpus.is_some())
+}
+
+fn cpu_dirs(root: &Path) -> Vec<(usize, PathBuf)> {
+    let mut cpus = fs::read_dir(root)
+        .unwrap_or_else(|err| panic!("Could not read {:?}: {}", root, err))
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let cpu = path
+                .file_name()?
+                .to_str()?
+                .strip_prefix("cpu")?
+                .parse::<usize>()
+                .ok()?;
+            Some((cpu, path))
+        })
+        .collect::<Vec<(usize, PathBuf)>>();
+    cpus.sort();
+    cpus
+}
+
+impl CpuState {
+    pub fn capture(root: &Path) -> Vec<CpuState> {
+        cpu_dirs(root)
+            .into_iter()
+            .map(|(cpu, dir)| CpuState {
+                cpu,
+                online: read_setting(&dir.join("online")).map(|online| online == "1"),
+                governor: read_setting(&dir.join("cpufreq").join("scaling_governor")),
+                min_freq: read_setting(&dir.join("cpufreq").join("scaling_min_freq")),
+                max_freq: read_setting(&dir.join("cpufreq").join("scaling_max_freq")),
+            })
+            .collect()
+    }
+
+    fn restore_online(&self, root: &Path) {
+        if let Some(online) = self.online {
+            let path = root.join(format!("cpu{}", self.cpu)).join("online");
+            write_setting(&path, if online { "1" } else { "0" });
+        }
+    }
+
+    /// Needs the CPU to be online
+    fn restore_frequency(&self, root: &Path) {
+        let cpufreq = root.join(format!("cpu{}", self.cpu)).join("cpufreq");
+        if let Some(governor) = &self.governor {
+            write_setting(&cpufreq.join("scaling_governor"), governor);
+        }
+        // The minimum can not be raised above the maximum, nor the maximum lowered below
+        // the minimum, so lower the minimum first when the maximum goes below it
+        let frequency = |value: Option<&str>| value.and_then(|value| value.parse::<u64>().ok());
+        let current_min = read_setting(&cpufreq.join("scaling_min_freq"));
+        let min_first = match (
+            frequency(self.max_freq.as_deref()),
+            frequency(current_min.as_deref()),
+        ) {
+            (Some(max_freq), Some(current_min)) => max_freq < current_min,
+            _ => false,
+        };
+        let mut writes = [
+            ("scaling_max_freq", &self.max_freq),
+            ("scaling_min_freq", &self.min_freq),
+        ];
+        if min_first {
+            writes.reverse();
+        }
+        for (file, value) in writes {
+            if let Some(value) = value {
+                write_setting(&cpufreq.join(file), value);
+            }
+        }
+    }
+}
+
+impl CpuSnapshot {
+    pub fn capture(root: &Path) -> CpuSnapshot {
+        let cpuset = cpuset_cpus();
+        CpuSnapshot {
+            taken: chrono::offset::Local::now().timestamp_millis().to_string(),
+            cpus: CpuState::capture(root),
+            // Leave the cpuset alone when it is unknown whether we created it
+            bench_cpuset: cpuset.as_ref().map_or(true, |cpus| cpus.is_some()),
+            bench_cpuset_cpus: cpuset.flatten(),
+            restored: false,
+        }
+    }
+
+    pub fn restore(&self, root: &Path) {
+        for state in &self.cpus {
+            state.restore_online(root);
+        }
+        for state in self.cpus.iter().filter(|state| state.online != Some(false)) {
+            state.restore_frequency(root);
+        }
+        // After the CPUs are online again, a cpuset can only hold online CPUs
+        if !self.bench_cpuset {
+            if cpuset_exists() == Some(true) {
+                match Command::new("cset").args(["set", "-d", CPUSET]).status() {
+                    Ok(status) if status.success() => println!("Destroyed cpuset {}", CPUSET),
+                    _ => println!("Failed to destroy cpuset {}", CPUSET),
+                }
+            }
+        } else if let Some(cpus) = &self.bench_cpuset_cpus {
+            if cpuset_cpus() != Some(Some(cpus.clone())) {
+                match Command::new("cset")
+                    .args(["set", "-c", cpus, CPUSET])
+                    .status()
+                {
+                    Ok(status) if status.success() => {
+                        println!("Restored cpuset {} to CPUs {}", CPUSET, cpus)
+                    }
+                    _ => println!("Failed to restore cpuset {} to CPUs {}", CPUSET, cpus),
+                }
+            }
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn load() -> Option<Snapshot> {
+        let content = fs::read_to_string(SNAPSHOT_PATH).ok()?;
+        Some(
+            serde_json::from_str(&content)
+                .unwrap_or_else(|err| panic!("Could not parse {}: {}", SNAPSHOT_PATH, err)),
+        )
+    }
+
+    pub fn store(&self) {
+        // Write to a temporary file first, a snapshot must never be lost halfway
+        let temporary = format!("{}.tmp", SNAPSHOT_PATH);
+        fs::write(&temporary, serde_json::to_string_pretty(self).unwrap())
+            .unwrap_or_else(|err| panic!("Could not write {}: {}", temporary, err));
+        fs::rename(&temporary, SNAPSHOT_PATH)
+            .unwrap_or_else(|err| panic!("Could not write {}: {}", SNAPSHOT_PATH, err));
+    }
+
+    /// `power restore`
+    pub fn restore(&mut self) {
+        if let Some(cpus) = &mut self.cpus {
+            println!("Restoring CPU settings from {}", cpus.taken);
+            cpus.restore(Path::new(cpu::SYSFS_CPU));
+            cpus.restored = true;
+        }
+        if let Some(paranoid) = &self.perf_event_paranoid {
+            write_setting(Path::new(PERF_EVENT_PARANOID), paranoid);
+        }
+        self.store();
+    }
+}
+
+/// Remember perf_event_paranoid and allow all perf events, for `power prep`.
+pub fn lower_perf_event_paranoid() {
+    let current = read_setting(Path::new(PERF_EVENT_PARANOID))
+        .unwrap_or_else(|| panic!("Could not read {}", PERF_EVENT_PARANOID));
+    // Already lowered by an earlier prep, keep the value from before that one
+    if current != "-1" {
+        let mut snapshot = Snapshot::load().unwrap_or_default();
+        snapshot.perf_event_paranoid = Some(current);
+        snapshot.store();
+    }
+    fs::write(PERF_EVENT_PARANOID, "-1").unwrap_or_else(|err| {
+        panic!(
+            "Failed to set {}, try running with sudo: {}",
+            PERF_EVENT_PARANOID, err
+        )
+    });
+    println!("Set {} to -1", PERF_EVENT_PARANOID);
+}
+
+fn restore_cpus(cpus: &CpuSnapshot) {
+    cpus.restore(Path::new(cpu::SYSFS_CPU));
+    let mut snapshot = Snapshot::load().unwrap_or_default();
+    snapshot.cpus = Some(CpuSnapshot {
+        restored: true,
+        ..cpus.clone()
+    });
+    snapshot.store();
+}
+
+/// Restores the CPU settings from before a run when dropped, also after a panic,
+/// and when the run is interrupted by a signal.
+pub struct RestoreGuard {
+    cpus: CpuSnapshot,
+    signals: Handle,
+}
+
+impl RestoreGuard {
+    pub fn new() -> RestoreGuard {
+        let mut snapshot = Snapshot::load().unwrap_or_default();
+        let cpus = match snapshot.cpus.take() {
+            // Capturing now would make the leftovers of that run the state to restore
+            Some(previous) if !previous.restored => {
+                println!(
+                    "Warning: the CPU settings from before the run at {} were never restored, restoring those instead. Use `power restore` to restore them now.",
+                    previous.taken
+                );
+                previous
+            }
+            _ => CpuSnapshot::capture(Path::new(cpu::SYSFS_CPU)),
+        };
+        snapshot.cpus = Some(cpus.clone());
+        snapshot.store();
+
+        let mut signals =
+            Signals::new([SIGINT, SIGTERM, SIGHUP]).expect("Could not install signal handler");
+        let handle = signals.handle();
+        let restore = cpus.clone();
+        thread::spawn(move || {
+            if let Some(signal) = signals.forever().next() {
+                println!("Received signal {}, restoring CPU settings", signal);
+                // The benchmark runs in its own process group and does not get the signal
+                execute::kill_running();
+                restore_cpus(&restore);
+                process::exit(128 + signal);
+            }
+        });
+
+        RestoreGuard {
+            cpus,
+            signals: handle,
+        }
+    }
+}
+
+impl Drop for RestoreGuard {
+    fn drop(&mut self) {
+        self.signals.close();
+        println!("Restoring CPU settings");
+        restore_cpus(&self.cpus);
+    }
+}
+
+#[test]
+fn test_restore_cpus() {
+    let root = tempfile::tempdir().unwrap();
+    let write = |cpu: usize, file: &str, value: &str| {
+        let path = root.path().join(format!("cpu{}", cpu)).join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, value).unwrap();
+    };
+    let read = |cpu: usize, file: &str| {
+        read_setting(&root.path().join(format!("cpu{}", cpu)).join(file)).unwrap()
+    };
+    // cpu0 can not be taken offline, cpu2 is offline and has no cpufreq
+    for cpu in 0..2 {
+        write(cpu, "cpufreq/scaling_governor", "powersave\n");
+        write(cpu, "cpufreq/scaling_min_freq", "800000\n");
+        write(cpu, "cpufreq/scaling_max_freq", "4500000\n");
+    }
+    write(1, "online", "1\n");
+    write(2, "online", "0\n");
+    fs::create_dir_all(root.path().join("cpufreq")).unwrap();
+
+    let states = CpuState::capture(root.path());
+    assert_eq!(
+        states.iter().map(|s| (s.cpu, s.online)).collect::<Vec<_>>(),
+        vec![(0, None), (1, Some(true)), (2, Some(false))]
+    );
+    assert_eq!(states[2].governor, None);
+
+    let snapshot = CpuSnapshot {
+        taken: "1684000000000".to_string(),
+        cpus: states,
+        bench_cpuset: true,
+        bench_cpuset_cpus: None,
+        restored: false,
+    };
+    write(1, "online", "0");
+    write(2, "online", "1");
+    write(0, "cpufreq/scaling_governor", "performance");
+    write(0, "cpufreq/scaling_min_freq", "4000000");
+    write(0, "cpufreq/scaling_max_freq", "4000000");
+    snapshot.restore(root.path());
+
+    assert_eq!(read(1, "online"), "1");
+    assert_eq!(read(2, "online"), "0");
+    assert_eq!(read(0, "cpufreq/scaling_governor"), "powersave");
+    assert_eq!(read(0, "cpufreq/scaling_min_freq"), "800000");
+    assert_eq!(read(0, "cpufreq/scaling_max_freq"), "4500000");
+}
@@ -0,0 +1,306 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use statrs::distribution::{ContinuousCDF, Normal};
+
+use crate::stats::quantile::{local_sort, percentile_of_sorted, Quantile};
+
+/// The statistic that is computed for every bootstrap resample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Estimator {
+    Mean,
+    Median,
+    /// Quantile in (0, 1)
+    Quantile(f64),
+}
+
+impl Estimator {
+    fn quantile(&self) -> Option<f64> {
+        match self {
+            Estimator::Mean => None,
+            Estimator::Median => Some(0.5),
+            Estimator::Quantile(q) => Some(*q),
+        }
+    }
+
+    pub fn estimate(&self, data: &[f64]) -> f64 {
+        match self.quantile() {
+            None => data.iter().sum::<f64>() / data.len() as f64,
+            Some(q) => data.percentile(q * 100.0),
+        }
+    }
+
+    /// Same as `estimate`, but reorders `data` instead of sorting a copy.
+    pub fn estimate_in_place(&self, data: &mut [f64]) -> f64 {
+        match self.quantile() {
+            None => data.iter().sum::<f64>() / data.len() as f64,
+            Some(q) => select_quantile(data, q),
+        }
+    }
+
+    /// Leave-one-out estimates, used for the BCa acceleration.
+    fn jackknife(&self, data: &[f64]) -> Vec<f64> {
+        let n = data.len();
+        match self.quantile() {
+            None => {
+                let sum = data.iter().sum::<f64>();
+                data.iter().map(|x| (sum - x) / (n - 1) as f64).collect()
+            }
+            Some(q) => {
+                // Removing the i-th smallest value shifts every later value one place down,
+                // so the quantile of the remainder can be read from the sorted data directly.
+                let mut sorted = data.to_vec();
+                local_sort(&mut sorted);
+                let rank = q * (n - 2) as f64;
+                let lower = rank.floor() as usize;
+                let fraction = rank - rank.floor();
+                (0..n)
+                    .map(|removed| {
+                        let at = |k: usize| if k < removed { sorted[k] } else { sorted[k + 1] };
+                        if lower + 1 >= n - 1 {
+                            at(lower)
+                        } else {
+                            at(lower) + (at(lower + 1) - at(lower)) * fraction
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+impl FromStr for Estimator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Estimator::Mean),
+            "median" => Ok(Estimator::Median),
+            quantile => match quantile.parse::<f64>() {
+                Ok(q) if 0.0 < q && q < 1.0 => Ok(Estimator::Quantile(q)),
+                _ => Err(format!(
+                    "Expected `mean`, `median` or a quantile between 0 and 1, got `{}`",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl Display for Estimator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Estimator::Mean => write!(f, "mean"),
+            Estimator::Median => write!(f, "median"),
+            Estimator::Quantile(q) => write!(f, "{}", q),
+        }
+    }
+}
+
+/// Linear interpolated quantile in O(n), matching `percentile_of_sorted`.
+fn select_quantile(data: &mut [f64], q: f64) -> f64 {
+    let rank = q * (data.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let (_, lo, above) = data.select_nth_unstable_by(lower, |a, b| a.total_cmp(b));
+    let lo = *lo;
+    if above.is_empty() {
+        return lo;
+    }
+    let hi = above.iter().cloned().fold(f64::INFINITY, f64::min);
+    lo + (hi - lo) * (rank - lower as f64)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfidenceInterval {
+    pub estimate: f64,
+    pub percentile_lower: f64,
+    pub percentile_upper: f64,
+    pub bca_lower: f64,
+    pub bca_upper: f64,
+}
+
+impl ConfidenceInterval {
+    /// Relative confidence interval width of the percentile interval
+    pub fn rciw_percentile(&self) -> f64 {
+        (self.percentile_upper - self.percentile_lower) / self.estimate
+    }
+
+    /// Relative confidence interval width of the BCa interval
+    pub fn rciw_bca(&self) -> f64 {
+        (self.bca_upper - self.bca_lower) / self.estimate
+    }
+}
+
+/// Seed derived from a base seed and a name, so every benchmark gets its own stream
+/// regardless of which other benchmarks are analysed.
+pub fn seed_for_name(seed: u64, name: &str) -> u64 {
+    // FNV-1a, stable across platforms and Rust versions
+    let hash = name.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    seed ^ hash
+}
+
+/// Nonparametric bootstrap with a seeded RNG, so results can be reproduced.
+pub struct Bootstrap {
+    rng: StdRng,
+    resamples: usize,
+}
+
+impl Bootstrap {
+    pub fn new(seed: u64, resamples: usize) -> Self {
+        Bootstrap {
+            rng: StdRng::seed_from_u64(seed),
+            resamples,
+        }
+    }
+
+    pub fn for_name(seed: u64, name: &str, resamples: usize) -> Self {
+        Self::new(seed_for_name(seed, name), resamples)
+    }
+
+    /// Draw `data.len()` values with replacement.
+    pub fn resample(&mut self, data: &[f64], into: &mut Vec<f64>) {
+        into.clear();
+        into.extend((0..data.len()).map(|_| data[self.rng.gen_range(0..data.len())]));
+    }
+
+    /// The estimator applied to every resample, sorted.
+    pub fn distribution(&mut self, data: &[f64], estimator: Estimator) -> Vec<f64> {
+        let mut buffer = Vec::with_capacity(data.len());
+        let mut estimates = (0..self.resamples)
+            .map(|_| {
+                self.resample(data, &mut buffer);
+                estimator.estimate_in_place(&mut buffer)
+            })
+            .collect::<Vec<f64>>();
+        local_sort(&mut estimates);
+        estimates
+    }
+
+    /// Same as `distribution`, for a statistic over two independent samples.
+    pub fn distribution2(
+        &mut self,
+        a: &[f64],
+        b: &[f64],
+        statistic: impl Fn(&mut [f64], &mut [f64]) -> f64,
+    ) -> Vec<f64> {
+        let mut buffer_a = Vec::with_capacity(a.len());
+        let mut buffer_b = Vec::with_capacity(b.len());
+        let mut estimates = (0..self.resamples)
+            .map(|_| {
+                self.resample(a, &mut buffer_a);
+                self.resample(b, &mut buffer_b);
+                statistic(&mut buffer_a, &mut buffer_b)
+            })
+            .collect::<Vec<f64>>();
+        local_sort(&mut estimates);
+        estimates
+    }
+
+    pub fn confidence_interval(
+        &mut self,
+        data: &[f64],
+        estimator: Estimator,
+        confidence_level: f64,
+    ) -> ConfidenceInterval {
+        let estimate = estimator.estimate(data);
+        let distribution = self.distribution(data, estimator);
+        let alpha = 1.0 - confidence_level;
+
+        let percentile_lower = percentile_of_sorted(&distribution, alpha / 2.0 * 100.0);
+        let percentile_upper = percentile_of_sorted(&distribution, (1.0 - alpha / 2.0) * 100.0);
+
+        let acceleration = acceleration(&estimator.jackknife(data));
+        let (bca_lower, bca_upper) =
+            bca_interval(&distribution, estimate, acceleration, confidence_level);
+
+        ConfidenceInterval {
+            estimate,
+            percentile_lower,
+            percentile_upper,
+            bca_lower,
+            bca_upper,
+        }
+    }
+}
+
+/// Jackknife estimate of the BCa acceleration constant.
+fn acceleration(jackknife: &[f64]) -> f64 {
+    let mean = jackknife.iter().sum::<f64>() / jackknife.len() as f64;
+    let (squares, cubes) = jackknife.iter().fold((0.0, 0.0), |(squares, cubes), x| {
+        let d = mean - x;
+        (squares + d * d, cubes + d * d * d)
+    });
+    if squares == 0.0 {
+        return 0.0;
+    }
+    cubes / (6.0 * squares.powf(1.5))
+}
+
+/// Bias-corrected and accelerated interval from a sorted bootstrap distribution.
+pub fn bca_interval(
+    distribution: &[f64],
+    estimate: f64,
+    acceleration: f64,
+    confidence_level: f64,
+) -> (f64, f64) {
+    let normal = Normal::new(0.0, 1.0).unwrap();
+    let resamples = distribution.len() as f64;
+
+    // Count ties as half, and keep the proportion away from 0 and 1 so z0 stays finite
+    let below = distribution.iter().filter(|x| **x < estimate).count() as f64;
+    let equal = distribution.iter().filter(|x| **x == estimate).count() as f64;
+    let proportion = ((below + equal / 2.0) / resamples)
+        .clamp(0.5 / resamples, 1.0 - 0.5 / resamples);
+    let z0 = normal.inverse_cdf(proportion);
+
+    let adjust = |alpha: f64| {
+        let z = z0 + normal.inverse_cdf(alpha);
+        normal.cdf(z0 + z / (1.0 - acceleration * z))
+    };
+
+    let alpha = 1.0 - confidence_level;
+    let lower = adjust(alpha / 2.0);
+    let upper = adjust(1.0 - alpha / 2.0);
+    (
+        percentile_of_sorted(distribution, lower * 100.0),
+        percentile_of_sorted(distribution, upper * 100.0),
+    )
+}
+
+#[test]
+fn test_bootstrap_is_reproducible() {
+    let data = (1..=50).map(|x| (x * x) as f64).collect::<Vec<f64>>();
+    let a = Bootstrap::new(42, 1000).confidence_interval(&data, Estimator::Median, 0.95);
+    let b = Bootstrap::new(42, 1000).confidence_interval(&data, Estimator::Median, 0.95);
+    assert_eq!(a.bca_lower, b.bca_lower);
+    assert_eq!(a.bca_upper, b.bca_upper);
+
+    assert!(a.percentile_lower < a.estimate && a.estimate < a.percentile_upper);
+    assert!(a.bca_lower < a.estimate && a.estimate < a.bca_upper);
+}
+
+#[test]
+fn test_confidence_interval_of_mean() {
+    // The interval of the mean is much narrower than the spread of the data itself
+    let data = (0..200).map(|x| (x % 20) as f64).collect::<Vec<f64>>();
+    let ci = Bootstrap::new(1, 2000).confidence_interval(&data, Estimator::Mean, 0.95);
+    assert_eq!(ci.estimate, 9.5);
+    assert!(ci.percentile_upper - ci.percentile_lower < 2.0);
+    assert!(ci.bca_upper - ci.bca_lower < 2.0);
+}
+
+#[test]
+fn test_jackknife_quantile() {
+    let data = vec![1.0, 2.0, 3.0, 4.0, 5.0, 7.0];
+    let jackknife = Estimator::Median.jackknife(&data);
+    for (i, estimate) in jackknife.iter().enumerate() {
+        let mut remaining = data.clone();
+        remaining.remove(i);
+        assert_eq!(*estimate, Estimator::Median.estimate(&remaining));
+    }
+}
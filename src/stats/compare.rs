@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::stats::bootstrap::{Bootstrap, Estimator};
+use crate::stats::hypothesis::{mann_whitney_u, Correction};
+use crate::stats::merge::{merge_runs, MergedSample};
+use crate::stats::metrics::Metric;
+use crate::stats::quantile::percentile_of_sorted;
+use crate::stats::{group_by_benchmark, read_csv, store_analysis, write_csv, BenchmarkKey};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verdict {
+    Regression,
+    Improvement,
+    NoChange,
+}
+
+/// The change of one benchmark from the baseline to the candidate.
+/// `change` is the relative difference of the medians, positive means slower.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Comparison {
+    pub rank: usize,
+    pub project: String,
+    pub bench_file: String,
+    pub benchmark: String,
+    pub baseline_samples: usize,
+    pub candidate_samples: usize,
+    pub baseline_median: f64,
+    pub candidate_median: f64,
+    pub change: f64,
+    pub change_lower: f64,
+    pub change_upper: f64,
+    pub cliffs_delta: f64,
+    pub u: f64,
+    pub z: f64,
+    pub p_value: f64,
+    pub p_adjusted: f64,
+    pub verdict: Verdict,
+}
+
+pub struct CompareOptions {
+    pub metric: Metric,
+    pub confidence_level: f64,
+    pub correction: Correction,
+    /// Minimal relative change of the median to report a regression or improvement
+    pub threshold: f64,
+    pub seed: u64,
+    pub resamples: usize,
+}
+
+/// A result set is either a merged dataset (`.csv`) or a run directory / timestamp in `data_dir`.
+pub fn load_result_set(data_dir: &Path, path: &Path) -> Vec<MergedSample> {
+    if path.is_file() {
+        read_csv(path)
+    } else {
+        merge_runs(data_dir, &[PathBuf::from(path)], false).0
+    }
+}
+
+fn relative_median_change(baseline: &mut [f64], candidate: &mut [f64]) -> f64 {
+    Estimator::Median.estimate_in_place(candidate) / Estimator::Median.estimate_in_place(baseline)
+        - 1.0
+}
+
+fn compare_benchmark(
+    key: &BenchmarkKey,
+    baseline: &[f64],
+    candidate: &[f64],
+    options: &CompareOptions,
+) -> Comparison {
+    let test = mann_whitney_u(candidate, baseline);
+
+    let baseline_median = Estimator::Median.estimate(baseline);
+    let candidate_median = Estimator::Median.estimate(candidate);
+
+    let name = format!("{}/{}/{}", key.0, key.1, key.2);
+    let distribution = Bootstrap::for_name(options.seed, &name, options.resamples)
+        .distribution2(baseline, candidate, relative_median_change);
+    let alpha = 1.0 - options.confidence_level;
+
+    let (project, bench_file, benchmark) = key.clone();
+    Comparison {
+        rank: 0,
+        project,
+        bench_file,
+        benchmark,
+        baseline_samples: baseline.len(),
+        candidate_samples: candidate.len(),
+        baseline_median,
+        candidate_median,
+        change: candidate_median / baseline_median - 1.0,
+        change_lower: percentile_of_sorted(&distribution, alpha / 2.0 * 100.0),
+        change_upper: percentile_of_sorted(&distribution, (1.0 - alpha / 2.0) * 100.0),
+        cliffs_delta: test.cliffs_delta,
+        u: test.u,
+        z: test.z,
+        p_value: test.p_value,
+        p_adjusted: test.p_value,
+        verdict: Verdict::NoChange,
+    }
+}
+
+pub fn compare_samples(
+    baseline: &[MergedSample],
+    candidate: &[MergedSample],
+    options: &CompareOptions,
+) -> Vec<Comparison> {
+    let baseline = group_by_benchmark(baseline);
+    let candidate = group_by_benchmark(candidate);
+
+    let mut comparisons = vec![];
+    for (key, baseline_samples) in &baseline {
+        let candidate_samples = match candidate.get(key) {
+            Some(samples) => samples,
+            None => {
+                println!("{:?} is missing from the candidate, skipping", key);
+                continue;
+            }
+        };
+        // A time and an instruction estimate have no relative change
+        let (baseline_unit, candidate_unit) = (baseline_samples[0].unit, candidate_samples[0].unit);
+        if baseline_unit != candidate_unit {
+            println!(
+                "{:?} is measured in {} in the baseline and in {} in the candidate, skipping",
+                key, baseline_unit, candidate_unit
+            );
+            continue;
+        }
+        let baseline_values = options.metric.values(baseline_samples);
+        let candidate_values = options.metric.values(candidate_samples);
+        if baseline_values.len() < 2 || candidate_values.len() < 2 {
+            println!("Not enough data to compare {:?}, skipping", key);
+            continue;
+        }
+        comparisons.push(compare_benchmark(
+            key,
+            &baseline_values,
+            &candidate_values,
+            options,
+        ));
+    }
+    for key in candidate.keys().filter(|key| !baseline.contains_key(*key)) {
+        println!("{:?} is missing from the baseline, skipping", key);
+    }
+
+    let p_values = comparisons.iter().map(|c| c.p_value).collect::<Vec<f64>>();
+    let alpha = 1.0 - options.confidence_level;
+    for (comparison, p_adjusted) in comparisons
+        .iter_mut()
+        .zip(options.correction.adjust(&p_values))
+    {
+        comparison.p_adjusted = p_adjusted;
+        let interval_excludes_zero = comparison.change_lower > 0.0 || comparison.change_upper < 0.0;
+        if p_adjusted < alpha
+            && interval_excludes_zero
+            && comparison.change.abs() >= options.threshold
+        {
+            comparison.verdict = if comparison.change > 0.0 {
+                Verdict::Regression
+            } else {
+                Verdict::Improvement
+            };
+        }
+    }
+
+    // Largest regressions first, then largest improvements, then the rest by p-value
+    comparisons.sort_by(|a, b| {
+        a.verdict.cmp(&b.verdict).then_with(|| match a.verdict {
+            Verdict::Regression => b.change.total_cmp(&a.change),
+            Verdict::Improvement => a.change.total_cmp(&b.change),
+            Verdict::NoChange => a.p_value.total_cmp(&b.p_value),
+        })
+    });
+    for (rank, comparison) in comparisons.iter_mut().enumerate() {
+        comparison.rank = rank + 1;
+    }
+    comparisons
+}
+
+/// `power stat compare`
+pub fn compare(
+    data_dir: &Path,
+    baseline: &Path,
+    candidate: &Path,
+    output: &Path,
+    options: &CompareOptions,
+    db: Option<&Path>,
+) {
+    let comparisons = compare_samples(
+        &load_result_set(data_dir, baseline),
+        &load_result_set(data_dir, candidate),
+        options,
+    );
+
+    let mut verdicts: BTreeMap<Verdict, usize> = BTreeMap::new();
+    for comparison in &comparisons {
+        *verdicts.entry(comparison.verdict).or_default() += 1;
+    }
+    println!("{:?}", verdicts);
+
+    for comparison in comparisons
+        .iter()
+        .filter(|c| c.verdict != Verdict::NoChange)
+    {
+        println!(
+            "{:>4} {:?}\t{:+.2}% [{:+.2}%, {:+.2}%]\tp={:.2e}\t{}/{}/{}",
+            comparison.rank,
+            comparison.verdict,
+            comparison.change * 100.0,
+            comparison.change_lower * 100.0,
+            comparison.change_upper * 100.0,
+            comparison.p_adjusted,
+            comparison.project,
+            comparison.bench_file,
+            comparison.benchmark
+        );
+    }
+
+    write_csv(output, &comparisons);
+    let input = format!("{} -> {}", baseline.to_string_lossy(), candidate.to_string_lossy());
+    store_analysis(db, "compare", &input, &comparisons);
+}
+
+#[test]
+fn test_compare_samples() {
+    let sample = |benchmark: &str, index: usize, time: f64| MergedSample {
+        iteration: 0,
+        run: "run".to_string(),
+        project: "project".to_string(),
+        bench_file: "bench".to_string(),
+        benchmark: benchmark.to_string(),
+        sample: index,
+        iterations: 1.0,
+        time,
+        unit: Default::default(),
+    };
+    let noise = |index: usize| (index % 7) as f64;
+
+    let baseline = (0..50)
+        .flat_map(|i| [sample("slower", i, 100.0 + noise(i)), sample("same", i, 100.0 + noise(i))])
+        .collect::<Vec<MergedSample>>();
+    let candidate = (0..50)
+        .flat_map(|i| [sample("slower", i, 120.0 + noise(i)), sample("same", i, 100.0 + noise(i))])
+        .collect::<Vec<MergedSample>>();
+
+    let options = CompareOptions {
+        metric: Metric::Sample,
+        confidence_level: 0.99,
+        correction: Correction::Holm,
+        threshold: 0.01,
+        seed: 0,
+        resamples: 1000,
+    };
+    let comparisons = compare_samples(&baseline, &candidate, &options);
+    assert_eq!(comparisons.len(), 2);
+    assert_eq!(comparisons[0].benchmark, "slower");
+    assert_eq!(comparisons[0].verdict, Verdict::Regression);
+    assert!(comparisons[0].change_lower > 0.15);
+    assert_eq!(comparisons[1].verdict, Verdict::NoChange);
+
+    let cycles = candidate
+        .into_iter()
+        .map(|sample| MergedSample {
+            unit: crate::harness::Unit::EstimatedCycles,
+            ..sample
+        })
+        .collect::<Vec<MergedSample>>();
+    assert!(compare_samples(&baseline, &cycles, &options).is_empty());
+}
@@ -0,0 +1,302 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use statrs::distribution::{ContinuousCDF, Normal, StudentsT};
+
+use crate::stats::hypothesis::{ranks, Correction};
+use crate::harness::Unit;
+use crate::stats::metrics::{Metric, Statistic};
+use crate::stats::quantile::mean;
+use crate::stats::{read_csv, store_analysis, write_csv};
+
+/// Language feature counts per (project, benchmark id)
+pub type Features = BTreeMap<(String, String), BTreeMap<String, u64>>;
+
+/// Stability measures of a `Statistic` that features are correlated with.
+const STABILITY: [(&str, fn(&Statistic) -> f64); 5] = [
+    ("rmad", |s| s.rmad),
+    ("cv", |s| s.std / s.mean),
+    ("rciw_boot", |s| s.rciw_boot),
+    ("rciw_bca", |s| s.rciw_bca),
+    ("rciw_mjhd", |s| s.rciw_mjhd),
+];
+
+/// Rank correlation of one language feature with one stability measure.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Correlation {
+    pub feature: String,
+    pub stability: String,
+    pub benchmarks: usize,
+    pub spearman_rho: f64,
+    pub spearman_p: f64,
+    pub spearman_p_adjusted: f64,
+    pub kendall_tau: f64,
+    pub kendall_p: f64,
+    pub kendall_p_adjusted: f64,
+}
+
+/// Reads the features written by `power coverage` to `coverage/<project>/<bench id>/<timestamp>.csv`.
+/// Only the latest file of every benchmark is used.
+pub fn read_language_features(coverage_dir: &Path) -> Features {
+    let pattern = coverage_dir.join("**").join("*.csv");
+    let mut paths = glob::glob(&pattern.to_string_lossy())
+        .expect("Invalid glob pattern")
+        .filter_map(|entry| entry.ok())
+        .collect::<Vec<_>>();
+    // Timestamps sort chronologically, so later files overwrite earlier ones
+    paths.sort();
+
+    let mut features = Features::new();
+    for path in paths {
+        let relative = path.parent().unwrap().strip_prefix(coverage_dir).unwrap();
+        let mut components = relative.iter().map(|c| c.to_string_lossy().to_string());
+        let project = match components.next() {
+            Some(project) => project,
+            None => continue,
+        };
+        let id = components.collect::<Vec<String>>().join("/");
+
+        let counts = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(&path)
+            .unwrap_or_else(|err| panic!("Could not read {:?}: {}", path, err))
+            .deserialize()
+            .map(|row| row.expect("Could not deserialize row"))
+            .collect::<BTreeMap<String, u64>>();
+        features.insert((project, id), counts);
+    }
+    features
+}
+
+/// Pearson correlation, NaN if either side is constant.
+fn pearson(x: &[f64], y: &[f64]) -> f64 {
+    let (mean_x, mean_y) = (mean(x), mean(y));
+    let covariance = x
+        .iter()
+        .zip(y)
+        .map(|(a, b)| (a - mean_x) * (b - mean_y))
+        .sum::<f64>();
+    let spread_x = x.iter().map(|a| (a - mean_x).powi(2)).sum::<f64>();
+    let spread_y = y.iter().map(|b| (b - mean_y).powi(2)).sum::<f64>();
+    covariance / (spread_x * spread_y).sqrt()
+}
+
+/// Spearman's rho with a two-sided p-value from the t distribution.
+pub fn spearman(x: &[f64], y: &[f64]) -> (f64, f64) {
+    let rho = pearson(&ranks(x).0, &ranks(y).0);
+    let df = x.len() as f64 - 2.0;
+    if rho.abs() >= 1.0 {
+        return (rho.signum(), 0.0);
+    }
+    let t = rho * (df / (1.0 - rho * rho)).sqrt();
+    let p = 2.0 * (1.0 - StudentsT::new(0.0, 1.0, df).unwrap().cdf(t.abs()));
+    (rho, p)
+}
+
+/// Sizes of the groups of equal values.
+fn tie_groups(data: &[f64]) -> Vec<f64> {
+    let mut counts: BTreeMap<u64, f64> = BTreeMap::new();
+    for value in data {
+        *counts.entry(value.to_bits()).or_default() += 1.0;
+    }
+    counts.into_values().filter(|t| *t > 1.0).collect()
+}
+
+/// Kendall's tau-b with a two-sided p-value from the tie corrected normal approximation.
+pub fn kendall(x: &[f64], y: &[f64]) -> (f64, f64) {
+    let n = x.len();
+    let mut s = 0.0;
+    for i in 0..n {
+        for j in i + 1..n {
+            // Concordant pairs add 1, discordant pairs subtract 1, ties add nothing
+            s += (x[i].total_cmp(&x[j]) as i8 * y[i].total_cmp(&y[j]) as i8) as f64;
+        }
+    }
+
+    let n = n as f64;
+    let (ties_x, ties_y) = (tie_groups(x), tie_groups(y));
+    let pairs = |ties: &[f64]| ties.iter().map(|t| t * (t - 1.0) / 2.0).sum::<f64>();
+    let n0 = n * (n - 1.0) / 2.0;
+    let tau = s / ((n0 - pairs(&ties_x)) * (n0 - pairs(&ties_y))).sqrt();
+
+    let sum = |ties: &[f64], f: fn(f64) -> f64| ties.iter().map(|t| f(*t)).sum::<f64>();
+    let v0 = n * (n - 1.0) * (2.0 * n + 5.0);
+    let vt = sum(&ties_x, |t| t * (t - 1.0) * (2.0 * t + 5.0));
+    let vu = sum(&ties_y, |t| t * (t - 1.0) * (2.0 * t + 5.0));
+    let v1 = sum(&ties_x, |t| t * (t - 1.0)) * sum(&ties_y, |t| t * (t - 1.0))
+        / (2.0 * n * (n - 1.0));
+    let v2 = sum(&ties_x, |t| t * (t - 1.0) * (t - 2.0))
+        * sum(&ties_y, |t| t * (t - 1.0) * (t - 2.0))
+        / (9.0 * n * (n - 1.0) * (n - 2.0));
+    let variance = (v0 - vt - vu) / 18.0 + v1 + v2;
+
+    let z = s / variance.sqrt();
+    let p = 2.0 * (1.0 - Normal::new(0.0, 1.0).unwrap().cdf(z.abs()));
+    (tau, p)
+}
+
+/// Every feature that occurs in any benchmark, features a benchmark lacks count as 0.
+fn feature_names(features: &Features) -> Vec<String> {
+    features
+        .values()
+        .flat_map(|counts| counts.keys().cloned())
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect()
+}
+
+pub fn correlate(
+    features: &Features,
+    statistics: &[&Statistic],
+    correction: Correction,
+) -> Vec<Correlation> {
+    let mut correlations = vec![];
+    for feature in feature_names(features) {
+        let counts = statistics
+            .iter()
+            .map(|s| {
+                let counts = &features[&(s.project.clone(), s.benchmark.clone())];
+                *counts.get(&feature).unwrap_or(&0) as f64
+            })
+            .collect::<Vec<f64>>();
+        if counts.iter().all(|count| *count == counts[0]) {
+            // Constant features have no ranking to correlate
+            continue;
+        }
+
+        for (stability, measure) in STABILITY {
+            let values = statistics.iter().map(|s| measure(s)).collect::<Vec<f64>>();
+            let (spearman_rho, spearman_p) = spearman(&counts, &values);
+            let (kendall_tau, kendall_p) = kendall(&counts, &values);
+            correlations.push(Correlation {
+                feature: feature.clone(),
+                stability: stability.to_string(),
+                benchmarks: counts.len(),
+                spearman_rho,
+                spearman_p,
+                spearman_p_adjusted: spearman_p,
+                kendall_tau,
+                kendall_p,
+                kendall_p_adjusted: kendall_p,
+            });
+        }
+    }
+
+    let spearman_p = correlations.iter().map(|c| c.spearman_p).collect::<Vec<f64>>();
+    let kendall_p = correlations.iter().map(|c| c.kendall_p).collect::<Vec<f64>>();
+    for ((correlation, spearman), kendall) in correlations
+        .iter_mut()
+        .zip(correction.adjust(&spearman_p))
+        .zip(correction.adjust(&kendall_p))
+    {
+        correlation.spearman_p_adjusted = spearman;
+        correlation.kendall_p_adjusted = kendall;
+    }
+    correlations
+}
+
+/// One row per benchmark with its stability measures followed by its feature counts.
+fn write_matrix(path: &Path, features: &Features, statistics: &[&Statistic]) {
+    println!("Writing {} rows to {}", statistics.len(), path.to_string_lossy());
+    let names = feature_names(features);
+    let mut writer = csv::Writer::from_path(path)
+        .unwrap_or_else(|err| panic!("Could not create {:?}: {}", path, err));
+
+    let header = ["project", "bench_file", "benchmark"]
+        .into_iter()
+        .chain(STABILITY.iter().map(|(name, _)| *name))
+        .chain(names.iter().map(String::as_str));
+    writer.write_record(header).unwrap();
+
+    for statistic in statistics {
+        let counts = &features[&(statistic.project.clone(), statistic.benchmark.clone())];
+        let record = [
+            statistic.project.clone(),
+            statistic.bench_file.clone(),
+            statistic.benchmark.clone(),
+        ]
+        .into_iter()
+        .chain(STABILITY.iter().map(|(_, measure)| measure(statistic).to_string()))
+        .chain(names.iter().map(|name| counts.get(name).unwrap_or(&0).to_string()));
+        writer.write_record(record).unwrap();
+    }
+    writer.flush().unwrap();
+}
+
+/// `power stat correlation`
+pub fn correlation(
+    statistics_path: &Path,
+    coverage_dir: &Path,
+    metric: Metric,
+    correction: Correction,
+    output: &Path,
+    matrix_path: &Path,
+    db: Option<&Path>,
+) {
+    let features = read_language_features(coverage_dir);
+    let statistics: Vec<Statistic> = read_csv(statistics_path);
+
+    let joined = statistics
+        .iter()
+        .filter(|s| s.metric == metric)
+        .filter(|s| {
+            // Instruction counts hardly vary, whatever the features of the benchmark
+            let timed = s.unit == Unit::Nanoseconds;
+            if !timed {
+                println!("{}/{} is measured in {}, skipping", s.project, s.benchmark, s.unit);
+            }
+            timed
+        })
+        .filter(|s| {
+            let found = features.contains_key(&(s.project.clone(), s.benchmark.clone()));
+            if !found {
+                println!("No language features for {}/{}, skipping", s.project, s.benchmark);
+            }
+            found
+        })
+        .collect::<Vec<&Statistic>>();
+    if joined.len() < 3 {
+        panic!("Need at least 3 benchmarks with both statistics and language features");
+    }
+
+    let correlations = correlate(&features, &joined, correction);
+    for correlation in correlations.iter().filter(|c| c.spearman_p_adjusted < 0.05) {
+        println!(
+            "{} ~ {}: rho={:.3} (p={:.2e}), tau={:.3} (p={:.2e})",
+            correlation.feature,
+            correlation.stability,
+            correlation.spearman_rho,
+            correlation.spearman_p_adjusted,
+            correlation.kendall_tau,
+            correlation.kendall_p_adjusted
+        );
+    }
+
+    write_csv(output, &correlations);
+    write_matrix(matrix_path, &features, &joined);
+    store_analysis(db, "correlation", &statistics_path.to_string_lossy(), &correlations);
+}
+
+#[test]
+fn test_rank_correlations() {
+    let x = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0];
+    let y = [2.0, 1.0, 4.0, 3.0, 6.0, 5.0, 8.0, 7.0];
+
+    // Every rank differs by one: 1 - 6 * 8 / (8 * 63)
+    let (rho, p) = spearman(&x, &y);
+    statrs::assert_almost_eq!(rho, 1.0 - 48.0 / 504.0, 1e-12);
+    assert!(p < 0.01);
+
+    // 4 of the 28 pairs are discordant
+    let (tau, p) = kendall(&x, &y);
+    statrs::assert_almost_eq!(tau, 20.0 / 28.0, 1e-12);
+    assert!(p < 0.05);
+
+    // Ties on both sides
+    let (tau, _) = kendall(&[1.0, 1.0, 2.0, 3.0], &[1.0, 2.0, 2.0, 3.0]);
+    statrs::assert_almost_eq!(tau, 0.8, 1e-12);
+
+    let (rho, p) = spearman(&x, &x.map(|v| -v));
+    assert_eq!((rho, p), (-1.0, 0.0));
+}
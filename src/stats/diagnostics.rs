@@ -0,0 +1,496 @@
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::path::Path;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::stats::bootstrap::seed_for_name;
+use crate::stats::merge::MergedSample;
+use crate::stats::quantile::{local_sort, mad, median, percentile_of_sorted, variance};
+use crate::stats::{group_by_benchmark, read_csv, store_analysis, write_csv, BenchmarkKey};
+
+pub struct DiagnosticOptions {
+    /// Significance level of the dip test
+    pub alpha: f64,
+    /// Uniform samples drawn to compute the p-value of the dip statistic
+    pub simulations: usize,
+    /// Peaks of the density below this fraction of the highest peak are ignored
+    pub min_peak: f64,
+    /// Number of points the density is evaluated at
+    pub grid: usize,
+    pub seed: u64,
+}
+
+/// Distribution shape and outliers of one benchmark, over all of its samples.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub project: String,
+    pub bench_file: String,
+    pub benchmark: String,
+    pub samples: usize,
+    pub bandwidth: f64,
+    pub modes: usize,
+    /// Locations of the modes, separated by `;`
+    pub mode_locations: String,
+    pub dip: f64,
+    pub dip_p_value: f64,
+    pub multimodal: bool,
+    pub low_severe: usize,
+    pub low_mild: usize,
+    pub high_mild: usize,
+    pub high_severe: usize,
+    /// Further than 3 scaled MADs from the median
+    pub mad_outliers: usize,
+}
+
+/// The mode the median of one RMIT iteration fell into.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IterationMode {
+    pub project: String,
+    pub bench_file: String,
+    pub benchmark: String,
+    pub iteration: usize,
+    pub run: String,
+    pub median: f64,
+    pub mode: usize,
+}
+
+/// Gaussian kernel density estimate with Silverman's rule of thumb for the bandwidth.
+pub struct Density {
+    pub bandwidth: f64,
+    pub grid: Vec<f64>,
+    pub density: Vec<f64>,
+}
+
+impl Density {
+    pub fn estimate(sorted: &[f64], points: usize) -> Density {
+        let n = sorted.len() as f64;
+        let iqr = percentile_of_sorted(sorted, 75.0) - percentile_of_sorted(sorted, 25.0);
+        let spread = variance(sorted).sqrt().min(iqr / 1.34);
+        let spread = if spread > 0.0 { spread } else { variance(sorted).sqrt() };
+        let bandwidth = 0.9 * spread * n.powf(-0.2);
+
+        let lo = sorted[0] - 3.0 * bandwidth;
+        let hi = sorted[sorted.len() - 1] + 3.0 * bandwidth;
+        let step = (hi - lo) / (points - 1) as f64;
+        let grid = (0..points).map(|i| lo + i as f64 * step).collect::<Vec<f64>>();
+
+        let norm = 1.0 / (n * bandwidth * (2.0 * PI).sqrt());
+        let density = grid
+            .iter()
+            .map(|x| {
+                // Only samples within 5 bandwidths contribute noticeably
+                let from = sorted.partition_point(|v| *v < x - 5.0 * bandwidth);
+                let to = sorted.partition_point(|v| *v <= x + 5.0 * bandwidth);
+                sorted[from..to]
+                    .iter()
+                    .map(|v| (-0.5 * ((x - v) / bandwidth).powi(2)).exp())
+                    .sum::<f64>()
+                    * norm
+            })
+            .collect();
+
+        Density {
+            bandwidth,
+            grid,
+            density,
+        }
+    }
+
+    /// Local maxima higher than `min_peak` times the highest peak, and the minima between them.
+    pub fn modes(&self, min_peak: f64) -> (Vec<f64>, Vec<f64>) {
+        let highest = self.density.iter().cloned().fold(0.0, f64::max);
+        let mut modes = vec![];
+        let mut antimodes = vec![];
+        let mut lowest_since_mode: Option<(f64, f64)> = None;
+
+        for i in 1..self.density.len() - 1 {
+            let (before, here, after) = (self.density[i - 1], self.density[i], self.density[i + 1]);
+            if here > before && here >= after && here >= min_peak * highest {
+                if let Some((location, _)) = lowest_since_mode.take() {
+                    antimodes.push(location);
+                }
+                modes.push(self.grid[i]);
+            } else if !modes.is_empty() {
+                match lowest_since_mode {
+                    Some((_, lowest)) if lowest <= here => {}
+                    _ => lowest_since_mode = Some((self.grid[i], here)),
+                }
+            }
+        }
+        (modes, antimodes)
+    }
+}
+
+/// Hartigan's dip statistic of sorted data, ported from the `diptest` R package (AS 217).
+pub fn dip(sorted: &[f64]) -> f64 {
+    let n = sorted.len();
+    if n < 2 || sorted[0] == sorted[n - 1] {
+        return 0.0;
+    }
+
+    // The algorithm is 1-indexed
+    let x = std::iter::once(0.0).chain(sorted.iter().cloned()).collect::<Vec<f64>>();
+    let mut mn = vec![0_usize; n + 1];
+    let mut mj = vec![0_usize; n + 1];
+    let mut gcm = vec![0_usize; n + 1];
+    let mut lcm = vec![0_usize; n + 1];
+
+    // Indices of the greatest convex minorant from the left
+    mn[1] = 1;
+    for j in 2..=n {
+        mn[j] = j - 1;
+        loop {
+            let mnj = mn[j];
+            let mnmnj = mn[mnj];
+            if mnj == 1
+                || (x[j] - x[mnj]) * ((mnj - mnmnj) as f64)
+                    < (x[mnj] - x[mnmnj]) * ((j - mnj) as f64)
+            {
+                break;
+            }
+            mn[j] = mnmnj;
+        }
+    }
+
+    // Indices of the least concave majorant from the right
+    mj[n] = n;
+    for k in (1..n).rev() {
+        mj[k] = k + 1;
+        loop {
+            let mjk = mj[k];
+            let mjmjk = mj[mjk];
+            if mjk == n
+                || (x[k] - x[mjk]) * (mjk as f64 - mjmjk as f64)
+                    < (x[mjk] - x[mjmjk]) * (k as f64 - mjk as f64)
+            {
+                break;
+            }
+            mj[k] = mjmjk;
+        }
+    }
+
+    let mut low = 1;
+    let mut high = n;
+    let mut dip = 1.0_f64;
+    loop {
+        // Change points of the GCM from high to low
+        let mut ic = 1;
+        gcm[1] = high;
+        while gcm[ic] > low {
+            gcm[ic + 1] = mn[gcm[ic]];
+            ic += 1;
+        }
+        let l_gcm = ic;
+        let mut ix = ic - 1;
+
+        // Change points of the LCM from low to high
+        let mut ih = 1;
+        lcm[1] = low;
+        while lcm[ih] < high {
+            lcm[ih + 1] = mj[lcm[ih]];
+            ih += 1;
+        }
+        let l_lcm = ih;
+        let mut iv = 2;
+
+        // Largest distance between the GCM and the LCM from low to high
+        let mut d = 0.0;
+        let mut ig = 1;
+        ih = 1;
+        if l_gcm != 2 || l_lcm != 2 {
+            loop {
+                let gcmix = gcm[ix];
+                let lcmiv = lcm[iv];
+                if gcmix > lcmiv {
+                    let gcmi1 = gcm[ix + 1];
+                    let dx = (lcmiv as f64 - gcmi1 as f64 + 1.0)
+                        - (x[lcmiv] - x[gcmi1]) * (gcmix - gcmi1) as f64
+                            / (x[gcmix] - x[gcmi1]);
+                    iv += 1;
+                    if dx >= d {
+                        d = dx;
+                        ig = ix + 1;
+                        ih = iv - 1;
+                    }
+                } else {
+                    let lcmiv1 = lcm[iv - 1];
+                    let dx = (x[gcmix] - x[lcmiv1]) * (lcmiv - lcmiv1) as f64
+                        / (x[lcmiv] - x[lcmiv1])
+                        - (gcmix as f64 - lcmiv1 as f64 - 1.0);
+                    ix -= 1;
+                    if dx >= d {
+                        d = dx;
+                        ig = ix + 1;
+                        ih = iv;
+                    }
+                }
+                ix = ix.max(1);
+                iv = iv.min(l_lcm);
+                if gcm[ix] == lcm[iv] {
+                    break;
+                }
+            }
+        } else {
+            // Both hulls are a straight line from low to high
+            d = 1.0;
+            ig = l_gcm;
+            ih = l_lcm;
+        }
+
+        if d < dip {
+            break;
+        }
+
+        // Dip of the convex minorant
+        let mut dip_l = 0.0_f64;
+        for j in ig..l_gcm {
+            let mut max_t = 1.0_f64;
+            let (j_, j1) = (gcm[j], gcm[j + 1]);
+            if j_ - j1 > 1 && x[j_] != x[j1] {
+                let c = (j_ - j1) as f64 / (x[j_] - x[j1]);
+                for jj in j1..=j_ {
+                    max_t = max_t.max((jj - j1 + 1) as f64 - (x[jj] - x[j1]) * c);
+                }
+            }
+            dip_l = dip_l.max(max_t);
+        }
+
+        // Dip of the concave majorant
+        let mut dip_u = 0.0_f64;
+        for j in ih..l_lcm {
+            let mut max_t = 1.0_f64;
+            let (j_, j1) = (lcm[j], lcm[j + 1]);
+            if j1 - j_ > 1 && x[j1] != x[j_] {
+                let c = (j1 - j_) as f64 / (x[j1] - x[j_]);
+                for jj in j_..=j1 {
+                    max_t = max_t.max((x[jj] - x[j_]) * c - (jj as f64 - j_ as f64 - 1.0));
+                }
+            }
+            dip_u = dip_u.max(max_t);
+        }
+
+        dip = dip.max(dip_l.max(dip_u));
+
+        // Without this check the loop may never end
+        if low == gcm[ig] && high == lcm[ih] {
+            break;
+        }
+        low = gcm[ig];
+        high = lcm[ih];
+    }
+
+    dip / (2 * n) as f64
+}
+
+/// Probability of a dip at least this large for a uniform sample of the same size.
+fn dip_p_value(dip_statistic: f64, n: usize, simulations: usize, rng: &mut StdRng) -> f64 {
+    let mut uniform = vec![0.0; n];
+    let at_least = (0..simulations)
+        .filter(|_| {
+            uniform.iter_mut().for_each(|u| *u = rng.gen::<f64>());
+            local_sort(&mut uniform);
+            dip(&uniform) >= dip_statistic
+        })
+        .count();
+    (at_least + 1) as f64 / (simulations + 1) as f64
+}
+
+/// Tukey's fences: (low severe, low mild, high mild, high severe)
+fn tukey_outliers(sorted: &[f64]) -> (usize, usize, usize, usize) {
+    let q1 = percentile_of_sorted(sorted, 25.0);
+    let q3 = percentile_of_sorted(sorted, 75.0);
+    let iqr = q3 - q1;
+    let count = |predicate: &dyn Fn(f64) -> bool| sorted.iter().filter(|x| predicate(**x)).count();
+
+    (
+        count(&|x| x < q1 - 3.0 * iqr),
+        count(&|x| q1 - 3.0 * iqr <= x && x < q1 - 1.5 * iqr),
+        count(&|x| q3 + 1.5 * iqr < x && x <= q3 + 3.0 * iqr),
+        count(&|x| q3 + 3.0 * iqr < x),
+    )
+}
+
+fn mad_outliers(data: &[f64]) -> usize {
+    let median = median(data);
+    // Scaled to be consistent with the standard deviation of a normal distribution
+    let scaled_mad = 1.4826 * mad(data, median);
+    if scaled_mad == 0.0 {
+        return data.iter().filter(|x| **x != median).count();
+    }
+    data.iter()
+        .filter(|x| ((*x - median) / scaled_mad).abs() > 3.0)
+        .count()
+}
+
+fn diagnose(
+    key: &BenchmarkKey,
+    samples: &[&MergedSample],
+    options: &DiagnosticOptions,
+) -> (Diagnostic, Vec<IterationMode>) {
+    let mut sorted = samples.iter().map(|s| s.value()).collect::<Vec<f64>>();
+    local_sort(&mut sorted);
+
+    let name = format!("{}/{}/{}", key.0, key.1, key.2);
+    let mut rng = StdRng::seed_from_u64(seed_for_name(options.seed, &name));
+
+    // All samples are equal, as with instruction counts, so there is no density to estimate
+    let constant = sorted[0] == sorted[sorted.len() - 1];
+    let (bandwidth, modes, antimodes, dip, dip_p_value) = if constant {
+        (0.0, vec![sorted[0]], vec![], 0.0, 1.0)
+    } else {
+        let density = Density::estimate(&sorted, options.grid);
+        let (modes, antimodes) = density.modes(options.min_peak);
+        let dip = dip(&sorted);
+        let dip_p_value = dip_p_value(dip, sorted.len(), options.simulations, &mut rng);
+        (density.bandwidth, modes, antimodes, dip, dip_p_value)
+    };
+    let (low_severe, low_mild, high_mild, high_severe) = tukey_outliers(&sorted);
+
+    let mut iterations: BTreeMap<usize, Vec<&MergedSample>> = BTreeMap::new();
+    for sample in samples {
+        iterations.entry(sample.iteration).or_default().push(sample);
+    }
+    let iteration_modes = iterations
+        .iter()
+        .map(|(iteration, samples)| {
+            let median = median(&samples.iter().map(|s| s.value()).collect::<Vec<f64>>());
+            IterationMode {
+                project: key.0.clone(),
+                bench_file: key.1.clone(),
+                benchmark: key.2.clone(),
+                iteration: *iteration,
+                run: samples[0].run.clone(),
+                median,
+                // Modes are separated by the antimodes between them
+                mode: antimodes.iter().filter(|a| **a < median).count(),
+            }
+        })
+        .collect();
+
+    let diagnostic = Diagnostic {
+        project: key.0.clone(),
+        bench_file: key.1.clone(),
+        benchmark: key.2.clone(),
+        samples: sorted.len(),
+        bandwidth,
+        modes: modes.len(),
+        mode_locations: modes
+            .iter()
+            .map(|m| m.to_string())
+            .collect::<Vec<String>>()
+            .join(";"),
+        dip,
+        dip_p_value,
+        multimodal: modes.len() > 1 && dip_p_value < options.alpha,
+        low_severe,
+        low_mild,
+        high_mild,
+        high_severe,
+        mad_outliers: mad_outliers(&sorted),
+    };
+    (diagnostic, iteration_modes)
+}
+
+/// `power stat diagnostics`
+pub fn diagnostics(
+    input: &Path,
+    output: &Path,
+    modes_path: &Path,
+    options: &DiagnosticOptions,
+    db: Option<&Path>,
+) {
+    let samples: Vec<MergedSample> = read_csv(input);
+
+    let mut diagnostics = vec![];
+    let mut iteration_modes = vec![];
+    for (key, benchmark_samples) in group_by_benchmark(&samples) {
+        if benchmark_samples.len() < 4 {
+            println!("Not enough samples to diagnose {:?}, skipping", key);
+            continue;
+        }
+        let (diagnostic, modes) = diagnose(&key, &benchmark_samples, options);
+        if diagnostic.multimodal {
+            println!(
+                "{}/{}/{} is multimodal: {} modes at {} (dip p={:.3})",
+                key.0, key.1, key.2, diagnostic.modes, diagnostic.mode_locations, diagnostic.dip_p_value
+            );
+        }
+        diagnostics.push(diagnostic);
+        iteration_modes.extend(modes);
+    }
+
+    println!(
+        "{} of {} benchmarks are multimodal",
+        diagnostics.iter().filter(|d| d.multimodal).count(),
+        diagnostics.len()
+    );
+    write_csv(output, &diagnostics);
+    write_csv(modes_path, &iteration_modes);
+    let input = input.to_string_lossy();
+    store_analysis(db, "diagnostics", &input, &diagnostics);
+    store_analysis(db, "diagnostics/modes", &input, &iteration_modes);
+}
+
+#[test]
+fn test_dip() {
+    // Evenly spaced values have the smallest possible dip, 1 / 2n
+    let uniform = (0..10).map(|x| x as f64).collect::<Vec<f64>>();
+    statrs::assert_almost_eq!(dip(&uniform), 0.05, 1e-12);
+
+    // Two separated clusters approach the dip of two point masses, 1 / 4
+    let bimodal = vec![1.0, 1.1, 1.2, 1.3, 5.0, 5.1, 5.2, 5.3];
+    assert!(dip(&bimodal) > 0.2 && dip(&bimodal) < 0.25);
+}
+
+#[test]
+fn test_bimodal_diagnostics() {
+    let key = ("project".to_string(), "bench".to_string(), "id".to_string());
+    // Iterations alternate between two levels, within an iteration values are spread evenly
+    let samples = (0..10)
+        .flat_map(|iteration| {
+            let level = if iteration % 2 == 0 { 100.0 } else { 120.0 };
+            (0..50).map(move |sample| MergedSample {
+                iteration,
+                run: iteration.to_string(),
+                project: "project".to_string(),
+                bench_file: "bench".to_string(),
+                benchmark: "id".to_string(),
+                sample,
+                iterations: 1.0,
+                time: level + (sample % 10) as f64 * 0.3,
+                unit: Default::default(),
+            })
+        })
+        .collect::<Vec<MergedSample>>();
+    let options = DiagnosticOptions {
+        alpha: 0.05,
+        simulations: 100,
+        min_peak: 0.05,
+        grid: 512,
+        seed: 0,
+    };
+
+    let (diagnostic, modes) = diagnose(&key, &samples.iter().collect::<Vec<_>>(), &options);
+    assert!(diagnostic.multimodal);
+    assert_eq!(diagnostic.modes, 2);
+    assert_eq!(modes.len(), 10);
+    assert_eq!(modes[0].mode, 0);
+    assert_eq!(modes[1].mode, 1);
+
+    let constant = samples
+        .into_iter()
+        .map(|sample| MergedSample {
+            time: 100.0,
+            ..sample
+        })
+        .collect::<Vec<MergedSample>>();
+    let (diagnostic, modes) = diagnose(&key, &constant.iter().collect::<Vec<_>>(), &options);
+    assert!(!diagnostic.multimodal);
+    assert_eq!(diagnostic.modes, 1);
+    assert_eq!(diagnostic.mode_locations, "100");
+    assert_eq!(diagnostic.dip_p_value, 1.0);
+    assert!(modes.iter().all(|mode| mode.mode == 0));
+}
@@ -0,0 +1,368 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use statrs::distribution::{ContinuousCDF, Normal};
+
+use crate::stats::correlation::kendall;
+use crate::stats::hypothesis::Correction;
+use crate::stats::merge::MergedSample;
+use crate::stats::quantile::{mean, median};
+use crate::stats::{group_by_benchmark, read_csv, store_analysis, write_csv, BenchmarkKey};
+
+pub struct DriftOptions {
+    pub alpha: f64,
+    pub correction: Correction,
+    /// Relative deviation from the benchmark median for an iteration to count as affected
+    pub threshold: f64,
+}
+
+/// Trend, step change and periodicity of the iteration medians of one benchmark.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Drift {
+    pub project: String,
+    pub bench_file: String,
+    pub benchmark: String,
+    pub iterations: usize,
+    /// Mann-Kendall tau of the medians against the iteration order
+    pub trend_tau: f64,
+    pub trend_p: f64,
+    pub trend_p_adjusted: f64,
+    /// Sen's slope, relative to the median, per iteration
+    pub slope_per_iteration: f64,
+    /// Sen's slope, relative to the median, per hour of wall-clock time
+    pub slope_per_hour: Option<f64>,
+    /// First iteration after the most likely step change (Pettitt), `None` without one
+    pub step_iteration: Option<usize>,
+    pub step_change: Option<f64>,
+    pub step_p: f64,
+    pub step_p_adjusted: f64,
+    /// Lag with the highest autocorrelation
+    pub period: usize,
+    pub autocorrelation: f64,
+    pub drifting: bool,
+    pub stepped: bool,
+    pub periodic: bool,
+}
+
+/// How many benchmarks deviated in the iterations that finished in one hour.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hour {
+    pub hour: String,
+    pub iterations: usize,
+    pub affected: usize,
+    pub affected_share: f64,
+    /// Median relative deviation from the benchmark median
+    pub median_deviation: f64,
+}
+
+/// Iteration medians of one benchmark in iteration order.
+struct Series {
+    iterations: Vec<usize>,
+    /// Milliseconds since the epoch at which the iteration finished, if the run is named that way
+    finished: Vec<Option<i64>>,
+    medians: Vec<f64>,
+}
+
+impl Series {
+    fn new(samples: &[&MergedSample]) -> Series {
+        let mut per_iteration: BTreeMap<usize, (&str, Vec<f64>)> = BTreeMap::new();
+        for sample in samples {
+            per_iteration
+                .entry(sample.iteration)
+                .or_insert((&sample.run, vec![]))
+                .1
+                .push(sample.value());
+        }
+
+        let mut series = Series {
+            iterations: vec![],
+            finished: vec![],
+            medians: vec![],
+        };
+        for (iteration, (run, values)) in per_iteration {
+            series.iterations.push(iteration);
+            series.finished.push(run.parse().ok());
+            series.medians.push(median(&values));
+        }
+        series
+    }
+}
+
+/// Median of the slopes between every pair of points.
+fn sens_slope(x: &[f64], y: &[f64]) -> f64 {
+    let mut slopes = vec![];
+    for i in 0..x.len() {
+        for j in i + 1..x.len() {
+            if x[j] != x[i] {
+                slopes.push((y[j] - y[i]) / (x[j] - x[i]));
+            }
+        }
+    }
+    median(&slopes)
+}
+
+/// Pettitt's test: (index of the first value after the change, approximate p-value).
+/// There is no change point if every split has the same values on both sides.
+pub fn pettitt(data: &[f64]) -> (Option<usize>, f64) {
+    let n = data.len();
+    let mut best = (0, 0.0_f64);
+    for t in 1..n {
+        let u = data[..t]
+            .iter()
+            .map(|before| {
+                data[t..]
+                    .iter()
+                    .map(|after| after.total_cmp(before) as i8 as f64)
+                    .sum::<f64>()
+            })
+            .sum::<f64>();
+        if u.abs() > best.1 {
+            best = (t, u.abs());
+        }
+    }
+
+    let n = n as f64;
+    let p = 2.0 * (-6.0 * best.1 * best.1 / (n * n * n + n * n)).exp();
+    (Some(best.0).filter(|step| *step > 0), p.min(1.0))
+}
+
+/// Autocorrelation at lags 1 to n / 2.
+fn autocorrelation(data: &[f64]) -> Vec<f64> {
+    let m = mean(data);
+    let total = data.iter().map(|x| (x - m).powi(2)).sum::<f64>();
+    (1..=data.len() / 2)
+        .map(|lag| {
+            data.iter()
+                .zip(&data[lag..])
+                .map(|(a, b)| (a - m) * (b - m))
+                .sum::<f64>()
+                / total
+        })
+        .collect()
+}
+
+fn drift(key: &BenchmarkKey, series: &Series, alpha: f64) -> Drift {
+    let order = series.iterations.iter().map(|i| *i as f64).collect::<Vec<f64>>();
+    let overall = median(&series.medians);
+    let relative = series
+        .medians
+        .iter()
+        .map(|m| m / overall)
+        .collect::<Vec<f64>>();
+
+    // Constant medians, like the estimated cycles of iai-callgrind, have no trend and no period
+    let constant = series.medians.iter().all(|m| *m == series.medians[0]);
+    let (trend_tau, trend_p) = if constant {
+        (0.0, 1.0)
+    } else {
+        kendall(&order, &series.medians)
+    };
+    let slope_per_hour = if series.finished.iter().all(Option::is_some) {
+        let hours = series
+            .finished
+            .iter()
+            .map(|ms| ms.unwrap() as f64 / 3_600_000.0)
+            .collect::<Vec<f64>>();
+        Some(sens_slope(&hours, &relative))
+    } else {
+        None
+    };
+
+    let (step, step_p) = pettitt(&series.medians);
+    let step_change = step
+        .map(|step| median(&series.medians[step..]) / median(&series.medians[..step]) - 1.0);
+
+    let correlations = if constant {
+        vec![]
+    } else {
+        autocorrelation(&series.medians)
+    };
+    let (period, autocorrelation) = correlations
+        .into_iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(lag, r)| (lag + 1, r))
+        .unwrap_or((0, 0.0));
+    // Approximate bound for the autocorrelation of white noise
+    let bound = Normal::new(0.0, 1.0).unwrap().inverse_cdf(1.0 - alpha / 2.0)
+        / (series.medians.len() as f64).sqrt();
+
+    Drift {
+        project: key.0.clone(),
+        bench_file: key.1.clone(),
+        benchmark: key.2.clone(),
+        iterations: series.medians.len(),
+        trend_tau,
+        trend_p,
+        trend_p_adjusted: trend_p,
+        slope_per_iteration: sens_slope(&order, &relative),
+        slope_per_hour,
+        step_iteration: step.map(|step| series.iterations[step]),
+        step_change,
+        step_p,
+        step_p_adjusted: step_p,
+        period,
+        autocorrelation,
+        drifting: false,
+        stepped: false,
+        periodic: period > 1 && autocorrelation > bound,
+    }
+}
+
+fn affected_hours(series: &[Series], threshold: f64) -> Vec<Hour> {
+    let mut hours: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for benchmark in series {
+        let overall = median(&benchmark.medians);
+        for (finished, median) in benchmark.finished.iter().zip(&benchmark.medians) {
+            let hour = match finished.and_then(|ms| Local.timestamp_millis_opt(ms).single()) {
+                Some(time) => time.format("%Y-%m-%d %H:00").to_string(),
+                None => continue,
+            };
+            hours.entry(hour).or_default().push(median / overall - 1.0);
+        }
+    }
+
+    hours
+        .into_iter()
+        .map(|(hour, deviations)| {
+            let affected = deviations.iter().filter(|d| d.abs() > threshold).count();
+            Hour {
+                hour,
+                iterations: deviations.len(),
+                affected,
+                affected_share: affected as f64 / deviations.len() as f64,
+                median_deviation: median(&deviations.iter().map(|d| d.abs()).collect::<Vec<_>>()),
+            }
+        })
+        .collect()
+}
+
+/// `power stat drift`
+pub fn drift_analysis(
+    input: &Path,
+    output: &Path,
+    hours_path: &Path,
+    options: &DriftOptions,
+    db: Option<&Path>,
+) {
+    let samples: Vec<MergedSample> = read_csv(input);
+
+    let mut all_series = vec![];
+    let mut drifts = vec![];
+    for (key, benchmark_samples) in group_by_benchmark(&samples) {
+        let series = Series::new(&benchmark_samples);
+        if series.medians.len() < 4 {
+            println!("{:?} has fewer than 4 iterations, skipping", key);
+            continue;
+        }
+        drifts.push(drift(&key, &series, options.alpha));
+        all_series.push(series);
+    }
+
+    let trend_p = drifts.iter().map(|d| d.trend_p).collect::<Vec<f64>>();
+    let step_p = drifts.iter().map(|d| d.step_p).collect::<Vec<f64>>();
+    for ((drift, trend), step) in drifts
+        .iter_mut()
+        .zip(options.correction.adjust(&trend_p))
+        .zip(options.correction.adjust(&step_p))
+    {
+        drift.trend_p_adjusted = trend;
+        drift.step_p_adjusted = step;
+        drift.drifting = trend < options.alpha;
+        drift.stepped = step < options.alpha && drift.step_iteration.is_some();
+    }
+
+    for drift in drifts
+        .iter()
+        .filter(|d| d.drifting || d.stepped || d.periodic)
+    {
+        let mut findings = vec![];
+        if drift.drifting {
+            findings.push(format!("drift {:+.3}%/iteration", drift.slope_per_iteration * 100.0));
+        }
+        if drift.stepped {
+            findings.push(format!(
+                "step {:+.2}% at iteration {}",
+                drift.step_change.unwrap() * 100.0,
+                drift.step_iteration.unwrap()
+            ));
+        }
+        if drift.periodic {
+            findings.push(format!("period of {} iterations", drift.period));
+        }
+        println!(
+            "{}/{}/{}: {}",
+            drift.project,
+            drift.bench_file,
+            drift.benchmark,
+            findings.join(", ")
+        );
+    }
+
+    let hours = affected_hours(&all_series, options.threshold);
+    let iterations = hours.iter().map(|h| h.iterations).sum::<usize>();
+    let affected = hours.iter().map(|h| h.affected).sum::<usize>();
+    if iterations > 0 {
+        let share = affected as f64 / iterations as f64;
+        println!(
+            "{:.1}% of iterations deviate more than {}% from their median",
+            share * 100.0,
+            options.threshold * 100.0
+        );
+        for hour in hours.iter().filter(|h| h.affected > 0 && h.affected_share > 2.0 * share) {
+            println!(
+                "\t{}: {} of {} iterations affected",
+                hour.hour, hour.affected, hour.iterations
+            );
+        }
+    }
+
+    write_csv(output, &drifts);
+    write_csv(hours_path, &hours);
+    let input = input.to_string_lossy();
+    store_analysis(db, "drift", &input, &drifts);
+    store_analysis(db, "drift/hours", &input, &hours);
+}
+
+#[test]
+fn test_drift() {
+    let key = ("project".to_string(), "bench".to_string(), "id".to_string());
+    let series = |medians: Vec<f64>| Series {
+        iterations: (0..medians.len()).collect(),
+        finished: (0..medians.len())
+            .map(|i| Some(1_684_000_000_000 + i as i64 * 1_800_000))
+            .collect(),
+        medians,
+    };
+
+    // Slowly heating up
+    let trend = drift(&key, &series((0..20).map(|i| 100.0 + i as f64).collect()), 0.05);
+    assert_eq!(trend.trend_tau, 1.0);
+    assert!(trend.trend_p < 0.001);
+    // One unit per half hour, relative to the median of 109.5
+    statrs::assert_almost_eq!(trend.slope_per_hour.unwrap(), 2.0 / 109.5, 1e-12);
+
+    // A background process started halfway
+    let step = (0..20).map(|i| if i < 12 { 100.0 } else { 110.0 } + (i % 2) as f64 * 0.1);
+    let step = drift(&key, &series(step.collect()), 0.05);
+    assert_eq!(step.step_iteration, Some(12));
+    assert!(step.step_p < 0.01);
+    assert!(step.step_change.unwrap() > 0.09);
+
+    // Two fast iterations, then two slow ones
+    let periodic = (0..20).map(|i| if (i / 2) % 2 == 0 { 100.0 } else { 105.0 } + i as f64 * 0.01);
+    let periodic = drift(&key, &series(periodic.collect()), 0.05);
+    assert!(periodic.periodic);
+    assert_eq!(periodic.period, 4);
+
+    // Deterministic estimated cycles
+    let constant = drift(&key, &series(vec![1000.0; 20]), 0.05);
+    assert_eq!((constant.trend_tau, constant.trend_p), (0.0, 1.0));
+    assert_eq!(constant.step_iteration, None);
+    assert_eq!(constant.step_change, None);
+    assert_eq!(constant.step_p, 1.0);
+    assert!(!constant.periodic);
+    assert_eq!(constant.autocorrelation, 0.0);
+}
@@ -0,0 +1,185 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use statrs::distribution::{ContinuousCDF, Normal};
+
+/// Average ranks (1-based) of `data`, ties get the mean of the ranks they span.
+/// Also returns the tie correction term `sum(t^3 - t)` over all groups of ties.
+pub fn ranks(data: &[f64]) -> (Vec<f64>, f64) {
+    let mut order = (0..data.len()).collect::<Vec<usize>>();
+    order.sort_by(|a, b| data[*a].total_cmp(&data[*b]));
+
+    let mut ranks = vec![0.0; data.len()];
+    let mut ties = 0.0;
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start;
+        while end + 1 < order.len() && data[order[end + 1]] == data[order[start]] {
+            end += 1;
+        }
+        let rank = (start + end) as f64 / 2.0 + 1.0;
+        for index in &order[start..=end] {
+            ranks[*index] = rank;
+        }
+        let t = (end - start + 1) as f64;
+        ties += t * t * t - t;
+        start = end + 1;
+    }
+    (ranks, ties)
+}
+
+#[derive(Debug, Clone)]
+pub struct MannWhitney {
+    /// U statistic of the first sample
+    pub u: f64,
+    /// Normal approximation, with tie and continuity correction
+    pub z: f64,
+    /// Two-sided p-value
+    pub p_value: f64,
+    /// Cliff's delta, the probability that a value of `a` is larger than one of `b` minus the reverse
+    pub cliffs_delta: f64,
+}
+
+/// Two-sided Mann-Whitney U test.
+pub fn mann_whitney_u(a: &[f64], b: &[f64]) -> MannWhitney {
+    let n1 = a.len() as f64;
+    let n2 = b.len() as f64;
+    let n = n1 + n2;
+
+    let combined = a.iter().chain(b.iter()).cloned().collect::<Vec<f64>>();
+    let (ranks, ties) = ranks(&combined);
+    let rank_sum = ranks[..a.len()].iter().sum::<f64>();
+
+    let u = rank_sum - n1 * (n1 + 1.0) / 2.0;
+    let mean = n1 * n2 / 2.0;
+    let variance = n1 * n2 / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+
+    let (z, p_value) = if variance > 0.0 {
+        let difference = u - mean;
+        let corrected = (difference.abs() - 0.5).max(0.0) * difference.signum();
+        let z = corrected / variance.sqrt();
+        let normal = Normal::new(0.0, 1.0).unwrap();
+        (z, (2.0 * (1.0 - normal.cdf(z.abs()))).min(1.0))
+    } else {
+        // Every value is identical
+        (0.0, 1.0)
+    };
+
+    MannWhitney {
+        u,
+        z,
+        p_value,
+        cliffs_delta: 2.0 * u / (n1 * n2) - 1.0,
+    }
+}
+
+/// How p-values are adjusted for testing many benchmarks at once.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Correction {
+    None,
+    Bonferroni,
+    Holm,
+    /// Benjamini-Hochberg false discovery rate
+    BenjaminiHochberg,
+}
+
+impl Correction {
+    /// Adjusted p-values, in the same order as `p_values`.
+    pub fn adjust(&self, p_values: &[f64]) -> Vec<f64> {
+        let m = p_values.len() as f64;
+        let mut order = (0..p_values.len()).collect::<Vec<usize>>();
+        order.sort_by(|a, b| p_values[*a].total_cmp(&p_values[*b]));
+        let mut adjusted = vec![0.0; p_values.len()];
+
+        match self {
+            Correction::None => adjusted.copy_from_slice(p_values),
+            Correction::Bonferroni => {
+                for (i, p) in p_values.iter().enumerate() {
+                    adjusted[i] = (p * m).min(1.0);
+                }
+            }
+            Correction::Holm => {
+                // Step-down, adjusted values can only increase
+                let mut running = 0.0_f64;
+                for (rank, index) in order.iter().enumerate() {
+                    running = running.max((p_values[*index] * (m - rank as f64)).min(1.0));
+                    adjusted[*index] = running;
+                }
+            }
+            Correction::BenjaminiHochberg => {
+                // Step-up, adjusted values can only decrease from the largest p-value down
+                let mut running = 1.0_f64;
+                for (rank, index) in order.iter().enumerate().rev() {
+                    running = running.min(p_values[*index] * m / (rank + 1) as f64);
+                    adjusted[*index] = running;
+                }
+            }
+        }
+        adjusted
+    }
+}
+
+impl FromStr for Correction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Correction::None),
+            "bonferroni" => Ok(Correction::Bonferroni),
+            "holm" => Ok(Correction::Holm),
+            "bh" => Ok(Correction::BenjaminiHochberg),
+            _ => Err(format!(
+                "Expected `none`, `bonferroni`, `holm` or `bh`, got `{}`",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for Correction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Correction::None => write!(f, "none"),
+            Correction::Bonferroni => write!(f, "bonferroni"),
+            Correction::Holm => write!(f, "holm"),
+            Correction::BenjaminiHochberg => write!(f, "bh"),
+        }
+    }
+}
+
+#[test]
+fn test_ranks() {
+    let (ranks, ties) = ranks(&[3.0, 1.0, 3.0, 2.0]);
+    assert_eq!(ranks, vec![3.5, 1.0, 3.5, 2.0]);
+    assert_eq!(ties, 6.0);
+}
+
+#[test]
+fn test_mann_whitney_u() {
+    // Every value of `b` is larger, so U is 0
+    let a = (0..20).map(|x| x as f64).collect::<Vec<f64>>();
+    let b = (100..120).map(|x| x as f64).collect::<Vec<f64>>();
+    let result = mann_whitney_u(&a, &b);
+    assert_eq!(result.u, 0.0);
+    assert_eq!(result.cliffs_delta, -1.0);
+    assert!(result.p_value < 0.0001);
+
+    let same = mann_whitney_u(&a, &a);
+    assert_eq!(same.cliffs_delta, 0.0);
+    assert_eq!(same.p_value, 1.0);
+}
+
+#[test]
+fn test_corrections() {
+    let p_values = [0.01, 0.04, 0.03, 0.005];
+    let expected = [
+        (Correction::Bonferroni, [0.04, 0.16, 0.12, 0.02]),
+        (Correction::Holm, [0.03, 0.06, 0.06, 0.02]),
+        (Correction::BenjaminiHochberg, [0.02, 0.04, 0.04, 0.02]),
+    ];
+    for (correction, expected) in expected {
+        for (p, e) in correction.adjust(&p_values).iter().zip(expected) {
+            statrs::assert_almost_eq!(*p, e, 1e-12);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::harness::Unit;
+use crate::stats::parse::{parse_run, BenchmarkResult};
+use crate::stats::{find_runs, run_name, store_analysis, write_csv, BenchmarkKey};
+
+/// A `Sample` tagged with the RMIT iteration it was measured in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergedSample {
+    pub iteration: usize,
+    pub run: String,
+    pub project: String,
+    pub bench_file: String,
+    pub benchmark: String,
+    pub sample: usize,
+    pub iterations: f64,
+    pub time: f64,
+    #[serde(default)]
+    pub unit: Unit,
+}
+
+impl MergedSample {
+    pub fn key(&self) -> BenchmarkKey {
+        (
+            self.project.clone(),
+            self.bench_file.clone(),
+            self.benchmark.clone(),
+        )
+    }
+
+    /// `unit` per iteration
+    pub fn value(&self) -> f64 {
+        self.time / self.iterations
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum Difference {
+    Missing,
+    Extra,
+}
+
+/// A benchmark that is missing from, or only present in, one iteration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeReport {
+    pub iteration: usize,
+    pub run: String,
+    pub project: String,
+    pub bench_file: String,
+    pub benchmark: String,
+    pub difference: Difference,
+}
+
+fn key_of(result: &BenchmarkResult) -> BenchmarkKey {
+    (
+        result.project.clone(),
+        result.bench_file.clone(),
+        result.id.full_id.clone(),
+    )
+}
+
+/// Resolve runs given on the command line, either as a path or as a timestamp in `data_dir`.
+fn resolve_runs(data_dir: &Path, runs: &[PathBuf]) -> Vec<PathBuf> {
+    if runs.is_empty() {
+        return find_runs(data_dir);
+    }
+
+    runs.iter()
+        .map(|run| {
+            if run.is_dir() {
+                run.clone()
+            } else if data_dir.join(run).is_dir() {
+                data_dir.join(run)
+            } else {
+                panic!("Could not find run {:?}", run)
+            }
+        })
+        .collect()
+}
+
+/// The benchmarks that are present in at least half of the iterations.
+fn expected_benchmarks(iterations: &[BTreeSet<BenchmarkKey>]) -> BTreeSet<BenchmarkKey> {
+    let mut counts: BTreeMap<&BenchmarkKey, usize> = BTreeMap::new();
+    for benchmarks in iterations {
+        for key in benchmarks {
+            *counts.entry(key).or_default() += 1;
+        }
+    }
+
+    counts
+        .into_iter()
+        .filter(|(_, count)| count * 2 >= iterations.len())
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+fn compare_iterations(
+    runs: &[String],
+    iterations: &[BTreeSet<BenchmarkKey>],
+) -> Vec<MergeReport> {
+    let expected = expected_benchmarks(iterations);
+    let mut report = vec![];
+
+    for (iteration, benchmarks) in iterations.iter().enumerate() {
+        let missing = expected
+            .difference(benchmarks)
+            .map(|key| (key, Difference::Missing));
+        let extra = benchmarks
+            .difference(&expected)
+            .map(|key| (key, Difference::Extra));
+
+        for ((project, bench_file, benchmark), difference) in missing.chain(extra) {
+            report.push(MergeReport {
+                iteration,
+                run: runs[iteration].clone(),
+                project: project.clone(),
+                bench_file: bench_file.clone(),
+                benchmark: benchmark.clone(),
+                difference,
+            });
+        }
+    }
+
+    report
+}
+
+/// Merge the given runs, or all runs in `data_dir`, into one dataset.
+/// With `intersect`, only benchmarks present in every iteration are kept.
+pub fn merge_runs(
+    data_dir: &Path,
+    runs: &[PathBuf],
+    intersect: bool,
+) -> (Vec<MergedSample>, Vec<MergeReport>) {
+    let runs = resolve_runs(data_dir, runs);
+    let results = runs.iter().map(|run| parse_run(run)).collect::<Vec<_>>();
+    let run_names = runs.iter().map(|run| run_name(run)).collect::<Vec<String>>();
+
+    let benchmark_sets = results
+        .iter()
+        .map(|run| run.iter().map(key_of).collect::<BTreeSet<BenchmarkKey>>())
+        .collect::<Vec<_>>();
+    let report = compare_iterations(&run_names, &benchmark_sets);
+
+    let common = benchmark_sets
+        .iter()
+        .skip(1)
+        .fold(benchmark_sets.first().cloned().unwrap_or_default(), |acc, set| {
+            acc.intersection(set).cloned().collect()
+        });
+
+    let samples = results
+        .iter()
+        .enumerate()
+        .flat_map(|(iteration, run)| {
+            run.iter()
+                .filter(|result| !intersect || common.contains(&key_of(result)))
+                .flat_map(move |result| {
+                    result.to_samples().into_iter().map(move |sample| MergedSample {
+                        iteration,
+                        run: sample.run,
+                        project: sample.project,
+                        bench_file: sample.bench_file,
+                        benchmark: sample.benchmark,
+                        sample: sample.sample,
+                        iterations: sample.iterations,
+                        time: sample.time,
+                        unit: sample.unit,
+                    })
+                })
+        })
+        .collect();
+
+    (samples, report)
+}
+
+/// `power stat merge`
+pub fn merge(
+    data_dir: &Path,
+    runs: &[PathBuf],
+    intersect: bool,
+    output: &Path,
+    report_path: &Path,
+    db: Option<&Path>,
+) {
+    let (samples, report) = merge_runs(data_dir, runs, intersect);
+
+    let mut per_iteration: BTreeMap<usize, Vec<&MergeReport>> = BTreeMap::new();
+    for entry in &report {
+        per_iteration.entry(entry.iteration).or_default().push(entry);
+    }
+
+    for (iteration, differences) in per_iteration {
+        let missing = differences
+            .iter()
+            .filter(|d| d.difference == Difference::Missing)
+            .count();
+        println!(
+            "Iteration {} ({}): {} missing, {} extra benchmark(s)",
+            iteration,
+            differences[0].run,
+            missing,
+            differences.len() - missing
+        );
+    }
+    if report.is_empty() {
+        println!("All iterations contain the same benchmarks");
+    }
+
+    write_csv(output, &samples);
+    write_csv(report_path, &report);
+    // Without runs every run in the data directory is merged
+    let input = if runs.is_empty() {
+        data_dir.to_string_lossy().to_string()
+    } else {
+        runs.iter()
+            .map(|run| run.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    store_analysis(db, "merge", &input, &samples);
+    store_analysis(db, "merge/report", &input, &report);
+}
+
+#[test]
+fn test_compare_iterations() {
+    let key = |id: &str| ("project".to_string(), "bench".to_string(), id.to_string());
+    let iterations = vec![
+        BTreeSet::from([key("a"), key("b")]),
+        BTreeSet::from([key("a"), key("b"), key("c")]),
+        BTreeSet::from([key("a")]),
+    ];
+    let runs = vec!["1".to_string(), "2".to_string(), "3".to_string()];
+
+    let report = compare_iterations(&runs, &iterations);
+    assert_eq!(report.len(), 2);
+    assert_eq!(report[0].iteration, 1);
+    assert_eq!(report[0].benchmark, "c");
+    assert_eq!(report[0].difference, Difference::Extra);
+    assert_eq!(report[1].iteration, 2);
+    assert_eq!(report[1].benchmark, "b");
+    assert_eq!(report[1].difference, Difference::Missing);
+}
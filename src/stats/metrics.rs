@@ -0,0 +1,236 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::harness::Unit;
+use crate::stats::bootstrap::{Bootstrap, Estimator};
+use crate::stats::merge::MergedSample;
+use crate::stats::quantile::{mad, mean, median, variance, Quantile};
+use crate::stats::{group_by_benchmark, read_csv, write_csv, BenchmarkKey};
+use crate::store::Store;
+
+/// Which values of a benchmark a `Statistic` is computed over.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// Every sample, in its unit per iteration
+    Sample,
+    /// The mean of the samples of each RMIT iteration
+    IterationMean,
+    /// The median of the samples of each RMIT iteration
+    IterationMedian,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 3] = [Metric::Sample, Metric::IterationMean, Metric::IterationMedian];
+
+    pub fn values(&self, samples: &[&MergedSample]) -> Vec<f64> {
+        match self {
+            Metric::Sample => samples.iter().map(|sample| sample.value()).collect(),
+            Metric::IterationMean => per_iteration(samples, mean),
+            Metric::IterationMedian => per_iteration(samples, median),
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sample" => Ok(Metric::Sample),
+            "iteration_mean" => Ok(Metric::IterationMean),
+            "iteration_median" => Ok(Metric::IterationMedian),
+            _ => Err(format!(
+                "Expected `sample`, `iteration_mean` or `iteration_median`, got `{}`",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for Metric {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Metric::Sample => write!(f, "sample"),
+            Metric::IterationMean => write!(f, "iteration_mean"),
+            Metric::IterationMedian => write!(f, "iteration_median"),
+        }
+    }
+}
+
+fn per_iteration(samples: &[&MergedSample], aggregate: fn(&[f64]) -> f64) -> Vec<f64> {
+    let mut iterations = samples.iter().map(|s| s.iteration).collect::<Vec<usize>>();
+    iterations.sort();
+    iterations.dedup();
+
+    iterations
+        .iter()
+        .map(|iteration| {
+            let values = samples
+                .iter()
+                .filter(|s| s.iteration == *iteration)
+                .map(|s| s.value())
+                .collect::<Vec<f64>>();
+            aggregate(&values)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Statistic {
+    pub project: String,
+    pub bench_file: String,
+    pub benchmark: String,
+    pub metric: Metric,
+    #[serde(default)]
+    pub unit: Unit,
+    pub samples: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub q1: f64,
+    pub q3: f64,
+    pub mad: f64,
+    pub rmad: f64,
+    pub std: f64,
+    pub var: f64,
+    pub estimator: String,
+    pub rciw_boot: f64,
+    pub rciw_bca: f64,
+    pub rciw_mjhd: f64,
+}
+
+pub fn data_to_statistics(
+    key: &BenchmarkKey,
+    metric: Metric,
+    data: &[f64],
+    confidence_level: f64,
+    bootstrap: &mut Bootstrap,
+    estimator: Estimator,
+) -> Statistic {
+    let mean = mean(data);
+    let var = variance(data);
+    let std = var.sqrt();
+    let (q1, median, q3) = data.quartiles();
+    let samples = data.len();
+
+    let min = data.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = data.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+    let mad = mad(data, median);
+    let rmad = mad / median;
+
+    let alpha = 1.0 - confidence_level;
+
+    // Harrel Davis RCIW
+    let hi = data.hd(confidence_level + alpha / 2.0);
+    let lo = data.hd(alpha / 2.0);
+    let rciw_mjhd = (hi - lo) / data.hd(0.5);
+
+    // Bootstrap RCIW of the estimator, percentile and BCa
+    let interval = bootstrap.confidence_interval(data, estimator, confidence_level);
+    let rciw_boot = interval.rciw_percentile();
+    let rciw_bca = interval.rciw_bca();
+
+    let (project, bench_file, benchmark) = key.clone();
+    Statistic {
+        project,
+        bench_file,
+        benchmark,
+        metric,
+        unit: Unit::default(),
+        samples,
+        min,
+        max,
+        mean,
+        median,
+        q1,
+        q3,
+        mad,
+        rmad,
+        std,
+        var,
+        estimator: estimator.to_string(),
+        rciw_boot,
+        rciw_bca,
+        rciw_mjhd,
+    }
+}
+
+pub fn compute_statistics(
+    samples: &[MergedSample],
+    confidence_level: f64,
+    estimator: Estimator,
+    seed: u64,
+    resamples: usize,
+) -> Vec<Statistic> {
+    let mut statistics = vec![];
+    for (key, benchmark_samples) in group_by_benchmark(samples) {
+        let name = format!("{}/{}/{}", key.0, key.1, key.2);
+        let mut bootstrap = Bootstrap::for_name(seed, &name, resamples);
+        for metric in Metric::ALL {
+            let data = metric.values(&benchmark_samples);
+            if data.len() < 2 {
+                println!("Not enough data for {:?} of {:?}, skipping", metric, key);
+                continue;
+            }
+            statistics.push(Statistic {
+                unit: benchmark_samples[0].unit,
+                ..data_to_statistics(
+                    &key,
+                    metric,
+                    &data,
+                    confidence_level,
+                    &mut bootstrap,
+                    estimator,
+                )
+            });
+        }
+    }
+    statistics
+}
+
+/// `power stat metrics`
+pub fn metrics(
+    input: &Path,
+    output: &Path,
+    confidence_level: f64,
+    estimator: Estimator,
+    seed: u64,
+    resamples: usize,
+    db: Option<&Path>,
+) {
+    assert!(
+        0.0 < confidence_level && confidence_level < 1.0,
+        "Confidence level must be between 0 and 1"
+    );
+    let samples: Vec<MergedSample> = read_csv(input);
+    let statistics = compute_statistics(&samples, confidence_level, estimator, seed, resamples);
+    write_csv(output, &statistics);
+    if let Some(db) = db {
+        Store::open(db).insert_statistics(input, &statistics);
+    }
+}
+
+#[test]
+fn test_data_to_statistics() {
+    let key = ("project".to_string(), "bench".to_string(), "id".to_string());
+    let data = vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0];
+    let mut bootstrap = Bootstrap::new(0, 1000);
+    let statistic =
+        data_to_statistics(&key, Metric::Sample, &data, 0.99, &mut bootstrap, Estimator::Mean);
+
+    assert_eq!(statistic.samples, 10);
+    assert_eq!(statistic.min, 1.0);
+    assert_eq!(statistic.max, 10.0);
+    assert_eq!(statistic.mean, 5.5);
+    assert_eq!(statistic.median, 5.5);
+    assert_eq!(statistic.mad, 2.5);
+    assert!(statistic.rciw_mjhd > 0.0);
+    assert!(statistic.rciw_boot > 0.0);
+    assert!(statistic.rciw_bca > 0.0);
+}
@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::harness::Unit;
+use crate::stats::merge::MergedSample;
+use crate::store::Store;
+
+pub(crate) mod bootstrap;
+pub(crate) mod compare;
+pub(crate) mod correlation;
+pub(crate) mod diagnostics;
+pub(crate) mod drift;
+pub(crate) mod hypothesis;
+pub(crate) mod merge;
+pub(crate) mod metrics;
+pub(crate) mod parse;
+pub(crate) mod quantile;
+pub(crate) mod repetitions;
+
+/// (project, bench file, benchmark id)
+pub type BenchmarkKey = (String, String, String);
+
+/// One sample of one benchmark in one run, as measured by its harness.
+/// `time` is the total measured in `unit` for `iterations` iterations.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sample {
+    pub run: String,
+    pub project: String,
+    pub bench_file: String,
+    pub benchmark: String,
+    pub sample: usize,
+    pub iterations: f64,
+    pub time: f64,
+    #[serde(default)]
+    pub unit: Unit,
+}
+
+/// All `data/<timestamp>` directories, ordered by timestamp.
+pub fn find_runs(data_dir: &Path) -> Vec<PathBuf> {
+    let mut runs = match fs::read_dir(data_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect::<Vec<PathBuf>>(),
+        Err(err) => panic!("Could not read data directory {:?}: {}", data_dir, err),
+    };
+
+    // Timestamps are milliseconds, but fall back to the name for anything else
+    runs.sort_by_key(|path| {
+        let name = run_name(path);
+        (name.parse::<u64>().unwrap_or(u64::MAX), name)
+    });
+    runs
+}
+
+pub fn run_name(run: &Path) -> String {
+    run.file_name().unwrap().to_string_lossy().to_string()
+}
+
+pub fn write_csv<T: Serialize>(path: &Path, rows: &[T]) {
+    println!("Writing {} rows to {}", rows.len(), path.to_string_lossy());
+    let mut writer = csv::Writer::from_path(path)
+        .unwrap_or_else(|err| panic!("Could not create {:?}: {}", path, err));
+    for row in rows {
+        writer.serialize(row).expect("Could not serialize row");
+    }
+    writer.flush().unwrap();
+}
+
+/// Also store the rows written to csv in the database, if there is one.
+pub fn store_analysis<T: Serialize>(db: Option<&Path>, analysis: &str, input: &str, rows: &[T]) {
+    if let Some(db) = db {
+        let mut store = Store::open(db);
+        store.insert_analysis(analysis, input, rows);
+        println!(
+            "Stored {} rows of {} in {}",
+            store.analysis_rows(analysis, input),
+            analysis,
+            db.to_string_lossy()
+        );
+    }
+}
+
+pub fn read_csv<T: DeserializeOwned>(path: &Path) -> Vec<T> {
+    csv::Reader::from_path(path)
+        .unwrap_or_else(|err| panic!("Could not read {:?}: {}", path, err))
+        .deserialize()
+        .map(|row| row.expect("Could not deserialize row"))
+        .collect()
+}
+
+pub fn group_by_benchmark(samples: &[MergedSample]) -> BTreeMap<BenchmarkKey, Vec<&MergedSample>> {
+    let mut groups: BTreeMap<BenchmarkKey, Vec<&MergedSample>> = BTreeMap::new();
+    for sample in samples {
+        groups.entry(sample.key()).or_default().push(sample);
+    }
+    groups
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::data::criterion::{BenchmarkId, Estimates, SampleData};
+use crate::harness::{HarnessKind, Unit};
+use crate::data::registry::Registry;
+use crate::stats::{find_runs, run_name, write_csv, Sample};
+
+/// Everything a harness stored for one benchmark in one run.
+#[derive(Debug, Clone)]
+pub struct BenchmarkResult {
+    pub run: String,
+    pub project: String,
+    pub bench_file: String,
+    pub id: BenchmarkId,
+    pub samples: SampleData,
+    pub estimates: Option<Estimates>,
+    pub unit: Unit,
+}
+
+impl BenchmarkResult {
+    pub fn to_samples(&self) -> Vec<Sample> {
+        self.samples
+            .iters
+            .iter()
+            .zip(self.samples.times.iter())
+            .enumerate()
+            .map(|(index, (iterations, time))| Sample {
+                run: self.run.clone(),
+                project: self.project.clone(),
+                bench_file: self.bench_file.clone(),
+                benchmark: self.id.full_id.clone(),
+                sample: index,
+                iterations: *iterations,
+                time: *time,
+                unit: self.unit,
+            })
+            .collect()
+    }
+
+    pub fn to_estimate_row(&self) -> Option<EstimateRow> {
+        let estimates = self.estimates.as_ref()?;
+        Some(EstimateRow {
+            run: self.run.clone(),
+            project: self.project.clone(),
+            bench_file: self.bench_file.clone(),
+            benchmark: self.id.full_id.clone(),
+            mean: estimates.mean.point_estimate,
+            mean_lower: estimates.mean.confidence_interval.lower_bound,
+            mean_upper: estimates.mean.confidence_interval.upper_bound,
+            median: estimates.median.point_estimate,
+            median_lower: estimates.median.confidence_interval.lower_bound,
+            median_upper: estimates.median.confidence_interval.upper_bound,
+            std_dev: estimates.std_dev.point_estimate,
+            median_abs_dev: estimates.median_abs_dev.point_estimate,
+            slope: estimates.slope.as_ref().map(|slope| slope.point_estimate),
+        })
+    }
+}
+
+/// Criterion's own point estimates, one row per benchmark per run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EstimateRow {
+    pub run: String,
+    pub project: String,
+    pub bench_file: String,
+    pub benchmark: String,
+    pub mean: f64,
+    pub mean_lower: f64,
+    pub mean_upper: f64,
+    pub median: f64,
+    pub median_lower: f64,
+    pub median_upper: f64,
+    pub std_dev: f64,
+    pub median_abs_dev: f64,
+    pub slope: Option<f64>,
+}
+
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let content = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&content) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            println!("Could not parse {:?}: {}", path, err);
+            None
+        }
+    }
+}
+
+/// Harnesses do not know about bench files, so map benchmark ids back using the registry.
+pub fn bench_files_by_id(project: &str) -> HashMap<String, String> {
+    match Registry::open_default().benchmarks(project) {
+        Ok(benchmarks) => benchmarks
+            .into_iter()
+            .map(|(bench_file, id)| (id, bench_file))
+            .collect(),
+        Err(err) => {
+            println!("{}, bench files will be unknown", err);
+            HashMap::new()
+        }
+    }
+}
+
+/// Read all benchmarks stored in `data/<timestamp>/<project>/<harness results>`.
+pub fn parse_run(run: &Path) -> Vec<BenchmarkResult> {
+    let run_id = run_name(run);
+    let mut results = vec![];
+
+    let mut projects = fs::read_dir(run)
+        .unwrap_or_else(|err| panic!("Could not read run {:?}: {}", run, err))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            HarnessKind::ALL
+                .iter()
+                .any(|harness| entry.path().join(harness.adapter().results_dir()).is_dir())
+        })
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect::<Vec<String>>();
+    projects.sort();
+
+    for project in projects {
+        let bench_files = bench_files_by_id(&project);
+        for harness in HarnessKind::ALL {
+            let dir = run.join(&project).join(harness.adapter().results_dir());
+            if !dir.is_dir() {
+                continue;
+            }
+
+            for measurement in harness.adapter().parse_results(&dir) {
+                results.push(BenchmarkResult {
+                    run: run_id.clone(),
+                    project: project.clone(),
+                    bench_file: measurement
+                        .bench_file
+                        .or_else(|| bench_files.get(&measurement.id.full_id).cloned())
+                        .unwrap_or_default(),
+                    id: measurement.id,
+                    samples: measurement.samples,
+                    estimates: measurement.estimates,
+                    unit: harness.adapter().unit(),
+                });
+            }
+        }
+    }
+
+    results.sort_by(|a, b| {
+        (&a.project, &a.bench_file, &a.id.full_id).cmp(&(&b.project, &b.bench_file, &b.id.full_id))
+    });
+    results
+}
+
+pub fn parse_all(data_dir: &Path) -> Vec<BenchmarkResult> {
+    find_runs(data_dir)
+        .iter()
+        .flat_map(|run| parse_run(run))
+        .collect()
+}
+
+/// `power stat parse`
+pub fn parse(data_dir: &Path, samples_path: &Path, estimates_path: &Path) {
+    let results = parse_all(data_dir);
+    println!("Found {} benchmark results in {:?}", results.len(), data_dir);
+
+    let samples = results
+        .iter()
+        .flat_map(BenchmarkResult::to_samples)
+        .collect::<Vec<Sample>>();
+    write_csv(samples_path, &samples);
+
+    let estimates = results
+        .iter()
+        .filter_map(BenchmarkResult::to_estimate_row)
+        .collect::<Vec<EstimateRow>>();
+    write_csv(estimates_path, &estimates);
+}
+
+#[test]
+fn test_parse_run() {
+    let data = tempfile::tempdir().unwrap();
+    let dir = data
+        .path()
+        .join("1684000000000")
+        .join("no_such_project")
+        .join("criterion")
+        .join("group")
+        .join("function")
+        .join("new");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("benchmark.json"),
+        r#"{"group_id":"group","function_id":"function","value_str":null,"throughput":null,
+            "full_id":"group/function","directory_name":"group/function","title":"group/function"}"#,
+    )
+    .unwrap();
+    fs::write(
+        dir.join("sample.json"),
+        r#"{"sampling_mode":"Linear","iters":[1.0,2.0,3.0],"times":[10.0,22.0,30.0]}"#,
+    )
+    .unwrap();
+
+    let results = parse_all(data.path());
+    assert_eq!(results.len(), 1);
+    assert!(results[0].estimates.is_none());
+
+    let samples = results[0].to_samples();
+    assert_eq!(samples.len(), 3);
+    assert_eq!(samples[1].run, "1684000000000");
+    assert_eq!(samples[1].benchmark, "group/function");
+    assert_eq!(samples[1].time / samples[1].iterations, 11.0);
+}
@@ -0,0 +1,111 @@
+use statrs::distribution::{Beta, ContinuousCDF};
+
+pub trait Quantile<T> {
+    fn quartiles(&self) -> (T, T, T);
+    fn percentile(&self, percentile: f64) -> T;
+    fn hd(&self, quantile: f64) -> T;
+}
+
+impl Quantile<f64> for [f64] {
+    fn quartiles(&self) -> (f64, f64, f64) {
+        let mut tmp = self.to_vec();
+        local_sort(&mut tmp);
+        let a = percentile_of_sorted(&tmp, 25_f64);
+        let b = percentile_of_sorted(&tmp, 50_f64);
+        let c = percentile_of_sorted(&tmp, 75_f64);
+        (a, b, c)
+    }
+
+    fn percentile(&self, pct: f64) -> f64 {
+        let mut tmp = self.to_vec();
+        local_sort(&mut tmp);
+        percentile_of_sorted(&tmp, pct)
+    }
+
+    /*
+        if nargin<2; q=.5;end
+    n=length(x);
+    m1=(n+1).*q;
+    m2=(n+1).*(1-q);
+    vec=1:length(x);
+    w=betacdf(vec./n,m1,m2)-betacdf((vec-1)./n,m1,m2);
+    y=sort(x);
+    thetaq=sum(w(:).*y(:));
+         */
+    fn hd(&self, quantile: f64) -> f64 {
+        let n = self.len() as f64;
+        let m1 = (n + 1.0) * quantile;
+        let m2 = (n + 1.0) * (1.0 - quantile);
+        let beta = Beta::new(m1, m2).unwrap();
+        let weights = (1..=self.len())
+            .map(|x| beta.cdf(x as f64 / n) - beta.cdf((x as f64 - 1.0) / n))
+            .collect::<Vec<f64>>();
+        let mut tmp = self.to_vec();
+        local_sort(&mut tmp);
+        tmp.iter().zip(weights).map(|(y, w)| w * y).sum()
+    }
+}
+
+pub fn local_sort(v: &mut [f64]) {
+    v.sort_by(|x: &f64, y: &f64| x.total_cmp(y));
+}
+
+pub fn mean(data: &[f64]) -> f64 {
+    data.iter().sum::<f64>() / data.len() as f64
+}
+
+/// Sample variance
+pub fn variance(data: &[f64]) -> f64 {
+    let mean = mean(data);
+    data.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (data.len() as f64 - 1.0)
+}
+
+pub fn median(data: &[f64]) -> f64 {
+    data.percentile(50.0)
+}
+
+/// Median absolute deviation around `median`
+pub fn mad(data: &[f64], median: f64) -> f64 {
+    let deviations = data.iter().map(|x| (x - median).abs()).collect::<Vec<f64>>();
+    deviations.percentile(50.0)
+}
+
+// Helper function: extract a value representing the `pct` percentile of a sorted sample-set, using
+// linear interpolation. If samples are not sorted, return nonsensical value.
+pub fn percentile_of_sorted(sorted_samples: &[f64], pct: f64) -> f64 {
+    assert!(!sorted_samples.is_empty());
+    if sorted_samples.len() == 1 {
+        return sorted_samples[0];
+    }
+    assert!(0.0 <= pct);
+    let hundred = 100_f64;
+    assert!(pct <= hundred);
+    if pct == hundred {
+        return sorted_samples[sorted_samples.len() - 1];
+    }
+    let length = (sorted_samples.len() - 1) as f64;
+    let rank = (pct / hundred) * length;
+    let lrank = rank.floor();
+    let d = rank - lrank;
+    let n = lrank as usize;
+    let lo = sorted_samples[n];
+    let hi = sorted_samples[n + 1];
+    lo + (hi - lo) * d
+}
+
+#[test]
+fn test_harreldavis() {
+    let a: Vec<f64> = vec![
+        77.0, 87., 88., 114., 151., 210., 219., 246., 253., 262., 296., 299., 306., 376., 428.,
+        515., 666., 1310., 2611.,
+    ];
+    statrs::assert_almost_eq!(a.hd(0.5), 271.72120054908913, 0.00000001);
+}
+
+#[test]
+fn test_quartiles() {
+    let a: Vec<f64> = vec![5.0, 1.0, 4.0, 2.0, 3.0];
+    assert_eq!(a.quartiles(), (2.0, 3.0, 4.0));
+    assert_eq!(mad(&a, median(&a)), 1.0);
+    assert_eq!(variance(&a), 2.5);
+}
@@ -0,0 +1,262 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use statrs::distribution::{ContinuousCDF, StudentsT};
+
+use crate::stats::bootstrap::{seed_for_name, Estimator};
+use crate::stats::merge::MergedSample;
+use crate::stats::quantile::{local_sort, mean, percentile_of_sorted, variance};
+use crate::stats::{group_by_benchmark, read_csv, store_analysis, write_csv, BenchmarkKey};
+
+pub struct RepetitionOptions {
+    /// RCIW targets, e.g. 0.01 for 1%
+    pub targets: Vec<f64>,
+    /// Candidate sample sizes, larger sizes than were collected are ignored
+    pub sample_sizes: Vec<usize>,
+    /// Number of random subsamples per (sample size, repetitions) pair
+    pub subsamples: usize,
+    pub confidence_level: f64,
+    /// Statistic of the samples within one iteration
+    pub estimator: Estimator,
+    pub seed: u64,
+}
+
+/// Expected RCIW of a benchmark when run with `repetitions` RMIT iterations of `sample_size` samples.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConvergencePoint {
+    pub project: String,
+    pub bench_file: String,
+    pub benchmark: String,
+    pub sample_size: usize,
+    pub repetitions: usize,
+    /// Median RCIW over all subsamples
+    pub rciw: f64,
+    /// 90th percentile RCIW over all subsamples
+    pub rciw_q90: f64,
+}
+
+/// The fewest repetitions needed to reach `target` at `sample_size`, if any.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Requirement {
+    pub project: String,
+    pub bench_file: String,
+    pub benchmark: String,
+    pub target: f64,
+    pub sample_size: usize,
+    pub repetitions: Option<usize>,
+}
+
+/// Relative width of the t confidence interval of the mean of the iteration estimates.
+fn rciw_t(values: &[f64], confidence_level: f64) -> f64 {
+    let n = values.len() as f64;
+    let t = StudentsT::new(0.0, 1.0, n - 1.0)
+        .unwrap()
+        .inverse_cdf(1.0 - (1.0 - confidence_level) / 2.0);
+    2.0 * t * (variance(values) / n).sqrt() / mean(values)
+}
+
+fn convergence_curve(
+    key: &BenchmarkKey,
+    iterations: &[Vec<f64>],
+    options: &RepetitionOptions,
+) -> Vec<ConvergencePoint> {
+    let name = format!("{}/{}/{}", key.0, key.1, key.2);
+    let mut rng = StdRng::seed_from_u64(seed_for_name(options.seed, &name));
+    let collected = iterations.iter().map(Vec::len).min().unwrap_or(0);
+
+    let mut curve = vec![];
+    let mut buffer = vec![];
+    for &sample_size in options.sample_sizes.iter().filter(|s| **s <= collected && **s > 0) {
+        for repetitions in 2..=iterations.len() {
+            let mut rciws = (0..options.subsamples)
+                .map(|_| {
+                    let estimates = sample(&mut rng, iterations.len(), repetitions)
+                        .iter()
+                        .map(|iteration| {
+                            let samples = &iterations[iteration];
+                            buffer.clear();
+                            buffer.extend(
+                                sample(&mut rng, samples.len(), sample_size)
+                                    .iter()
+                                    .map(|index| samples[index]),
+                            );
+                            options.estimator.estimate_in_place(&mut buffer)
+                        })
+                        .collect::<Vec<f64>>();
+                    rciw_t(&estimates, options.confidence_level)
+                })
+                .collect::<Vec<f64>>();
+            local_sort(&mut rciws);
+
+            curve.push(ConvergencePoint {
+                project: key.0.clone(),
+                bench_file: key.1.clone(),
+                benchmark: key.2.clone(),
+                sample_size,
+                repetitions,
+                rciw: percentile_of_sorted(&rciws, 50.0),
+                rciw_q90: percentile_of_sorted(&rciws, 90.0),
+            });
+        }
+    }
+    curve
+}
+
+fn requirements(curve: &[ConvergencePoint], targets: &[f64]) -> Vec<Requirement> {
+    let mut sample_sizes = curve.iter().map(|p| p.sample_size).collect::<Vec<usize>>();
+    sample_sizes.dedup();
+
+    let mut requirements = vec![];
+    for &target in targets {
+        for &sample_size in &sample_sizes {
+            let point = curve
+                .iter()
+                .filter(|p| p.sample_size == sample_size)
+                .find(|p| p.rciw <= target);
+            let first = &curve[0];
+            requirements.push(Requirement {
+                project: first.project.clone(),
+                bench_file: first.bench_file.clone(),
+                benchmark: first.benchmark.clone(),
+                target,
+                sample_size,
+                repetitions: point.map(|p| p.repetitions),
+            });
+        }
+    }
+    requirements
+}
+
+/// For every target, the sample size that lets the most benchmarks converge,
+/// with the repetitions needed for all of those.
+fn recommend(
+    requirements: &[Requirement],
+    target: f64,
+) -> Option<(usize, usize, Vec<&Requirement>)> {
+    let mut per_sample_size: BTreeMap<usize, Vec<&Requirement>> = BTreeMap::new();
+    for requirement in requirements.iter().filter(|r| r.target == target) {
+        per_sample_size
+            .entry(requirement.sample_size)
+            .or_default()
+            .push(requirement);
+    }
+
+    per_sample_size
+        .into_iter()
+        .map(|(sample_size, requirements)| {
+            let repetitions = requirements
+                .iter()
+                .filter_map(|r| r.repetitions)
+                .max()
+                .unwrap_or(0);
+            let never = requirements
+                .into_iter()
+                .filter(|r| r.repetitions.is_none())
+                .collect::<Vec<&Requirement>>();
+            (sample_size, repetitions, never)
+        })
+        .min_by_key(|(sample_size, repetitions, never)| (never.len(), *repetitions, *sample_size))
+}
+
+/// `power stat repetitions`
+pub fn repetitions(
+    input: &Path,
+    curve_path: &Path,
+    requirements_path: &Path,
+    options: &RepetitionOptions,
+    db: Option<&Path>,
+) {
+    let samples: Vec<MergedSample> = read_csv(input);
+
+    let mut curve = vec![];
+    let mut required = vec![];
+    for (key, benchmark_samples) in group_by_benchmark(&samples) {
+        let mut iterations: BTreeMap<usize, Vec<f64>> = BTreeMap::new();
+        for sample in benchmark_samples {
+            iterations.entry(sample.iteration).or_default().push(sample.value());
+        }
+        if iterations.len() < 2 {
+            println!("{:?} has fewer than 2 iterations, skipping", key);
+            continue;
+        }
+
+        let benchmark_curve =
+            convergence_curve(&key, &iterations.into_values().collect::<Vec<_>>(), options);
+        if benchmark_curve.is_empty() {
+            continue;
+        }
+        required.extend(requirements(&benchmark_curve, &options.targets));
+        curve.extend(benchmark_curve);
+    }
+
+    for &target in &options.targets {
+        match recommend(&required, target) {
+            None => println!("No data to recommend settings for a RCIW of {}", target),
+            Some((_, 0, _)) => {
+                println!("No benchmark reaches a RCIW of {}", target);
+            }
+            Some((sample_size, repetitions, never)) => {
+                println!(
+                    "RCIW <= {}%: `power run -r {} -s {}`",
+                    target * 100.0,
+                    repetitions,
+                    sample_size
+                );
+                if !never.is_empty() {
+                    println!("{} benchmark(s) never converge:", never.len());
+                    for requirement in never {
+                        println!(
+                            "\t{}/{}/{}",
+                            requirement.project, requirement.bench_file, requirement.benchmark
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    write_csv(curve_path, &curve);
+    write_csv(requirements_path, &required);
+    let input = input.to_string_lossy();
+    store_analysis(db, "repetitions/curve", &input, &curve);
+    store_analysis(db, "repetitions", &input, &required);
+}
+
+#[test]
+fn test_convergence_curve() {
+    let key = ("project".to_string(), "bench".to_string(), "id".to_string());
+    // Iteration medians spread from 97 to 103, so more repetitions narrow the interval
+    let iterations = (0..20)
+        .map(|i| {
+            let offset = (i as f64 - 9.5) * 0.3;
+            (0..50).map(|s| 100.0 + offset + (s % 3) as f64 * 0.1).collect::<Vec<f64>>()
+        })
+        .collect::<Vec<Vec<f64>>>();
+    let options = RepetitionOptions {
+        targets: vec![0.03, 0.0001],
+        sample_sizes: vec![10, 50, 100],
+        subsamples: 20,
+        confidence_level: 0.95,
+        estimator: Estimator::Median,
+        seed: 0,
+    };
+
+    let curve = convergence_curve(&key, &iterations, &options);
+    // Sample size 100 is more than was collected
+    assert_eq!(curve.len(), 2 * 19);
+    assert!(curve[18].rciw < curve[1].rciw);
+
+    let required = requirements(&curve, &options.targets);
+    assert!(required[0].repetitions.unwrap() > 2);
+    assert!(required[2].repetitions.is_none());
+
+    let (_, repetitions, never) = recommend(&required, 0.03).unwrap();
+    assert!(repetitions > 2);
+    assert!(never.is_empty());
+    let (_, _, never) = recommend(&required, 0.0001).unwrap();
+    assert_eq!(never.len(), 1);
+}
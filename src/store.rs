@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::data::project::Project;
+use crate::stats::correlation::read_language_features;
+use crate::stats::metrics::Statistic;
+use crate::stats::parse::{bench_files_by_id, parse_run};
+use crate::stats::{find_runs, run_name};
+
+/// The database `power run`, `power coverage`, `power instructions`, `power project parse` and the
+/// `power stat` commands write to.
+pub const DEFAULT_PATH: &str = "power.db";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS projects (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS bench_files (
+    id INTEGER PRIMARY KEY,
+    project_id INTEGER NOT NULL REFERENCES projects(id),
+    name TEXT NOT NULL,
+    source TEXT NOT NULL,
+    features TEXT NOT NULL,
+    UNIQUE (project_id, name)
+);
+-- The bench file is empty when it could not be found in the project json
+CREATE TABLE IF NOT EXISTS benchmarks (
+    id INTEGER PRIMARY KEY,
+    project_id INTEGER NOT NULL REFERENCES projects(id),
+    bench_file TEXT NOT NULL,
+    name TEXT NOT NULL,
+    UNIQUE (project_id, bench_file, name)
+);
+-- One row per `data/<timestamp>` directory
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS samples (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    benchmark_id INTEGER NOT NULL REFERENCES benchmarks(id),
+    sample INTEGER NOT NULL,
+    iterations REAL NOT NULL,
+    time REAL NOT NULL,
+    unit TEXT NOT NULL DEFAULT 'nanoseconds',
+    PRIMARY KEY (run_id, benchmark_id, sample)
+);
+CREATE TABLE IF NOT EXISTS language_features (
+    benchmark_id INTEGER NOT NULL REFERENCES benchmarks(id),
+    recorded TEXT NOT NULL,
+    feature TEXT NOT NULL,
+    count INTEGER NOT NULL,
+    PRIMARY KEY (benchmark_id, recorded, feature)
+);
+CREATE TABLE IF NOT EXISTS instructions (
+    benchmark_id INTEGER NOT NULL PRIMARY KEY REFERENCES benchmarks(id),
+    count INTEGER NOT NULL
+);
+-- Output of `power stat metrics`, per input dataset
+CREATE TABLE IF NOT EXISTS statistics (
+    input TEXT NOT NULL,
+    benchmark_id INTEGER NOT NULL REFERENCES benchmarks(id),
+    metric TEXT NOT NULL,
+    samples INTEGER NOT NULL,
+    min REAL NOT NULL,
+    max REAL NOT NULL,
+    mean REAL NOT NULL,
+    median REAL NOT NULL,
+    q1 REAL NOT NULL,
+    q3 REAL NOT NULL,
+    mad REAL NOT NULL,
+    rmad REAL NOT NULL,
+    std REAL NOT NULL,
+    var REAL NOT NULL,
+    estimator TEXT NOT NULL,
+    rciw_boot REAL NOT NULL,
+    rciw_bca REAL NOT NULL,
+    rciw_mjhd REAL NOT NULL,
+    unit TEXT NOT NULL DEFAULT 'nanoseconds',
+    PRIMARY KEY (input, benchmark_id, metric)
+);
+-- Output of the other `power stat` commands, one JSON object per row of their csv output.
+-- The benchmark is empty for rows that are not about one benchmark, like correlations.
+CREATE TABLE IF NOT EXISTS analyses (
+    analysis TEXT NOT NULL,
+    input TEXT NOT NULL,
+    row INTEGER NOT NULL,
+    benchmark_id INTEGER REFERENCES benchmarks(id),
+    result TEXT NOT NULL,
+    PRIMARY KEY (analysis, input, row)
+);
+";
+
+pub struct Store {
+    connection: Connection,
+}
+
+fn project_id(connection: &Connection, project: &str) -> i64 {
+    connection
+        .execute(
+            "INSERT OR IGNORE INTO projects (name) VALUES (?1)",
+            [project],
+        )
+        .expect("Could not insert project");
+    connection
+        .query_row(
+            "SELECT id FROM projects WHERE name = ?1",
+            [project],
+            |row| row.get(0),
+        )
+        .expect("Could not find project")
+}
+
+fn benchmark_id(connection: &Connection, project: &str, bench_file: &str, benchmark: &str) -> i64 {
+    let project_id = project_id(connection, project);
+    connection
+        .execute(
+            "INSERT OR IGNORE INTO benchmarks (project_id, bench_file, name) VALUES (?1, ?2, ?3)",
+            params![project_id, bench_file, benchmark],
+        )
+        .expect("Could not insert benchmark");
+    connection
+        .query_row(
+            "SELECT id FROM benchmarks WHERE project_id = ?1 AND bench_file = ?2 AND name = ?3",
+            params![project_id, bench_file, benchmark],
+            |row| row.get(0),
+        )
+        .expect("Could not find benchmark")
+}
+
+impl Store {
+    pub fn open(path: &Path) -> Store {
+        let connection = Connection::open(path)
+            .unwrap_or_else(|err| panic!("Could not open database {:?}: {}", path, err));
+        connection
+            .execute_batch(SCHEMA)
+            .expect("Could not create database schema");
+        // Databases from before samples had a unit only hold nanoseconds
+        for table in ["samples", "statistics"] {
+            if connection
+                .prepare(&format!("SELECT unit FROM {} LIMIT 0", table))
+                .is_err()
+            {
+                connection
+                    .execute(
+                        &format!(
+                            "ALTER TABLE {} ADD COLUMN unit TEXT NOT NULL DEFAULT 'nanoseconds'",
+                            table
+                        ),
+                        [],
+                    )
+                    .expect("Could not add unit to database schema");
+            }
+        }
+        Store { connection }
+    }
+
+    pub fn open_default() -> Store {
+        Store::open(Path::new(DEFAULT_PATH))
+    }
+
+    pub fn insert_project(&mut self, project: &Project) {
+        let transaction = self.connection.transaction().unwrap();
+        let project_id = project_id(&transaction, &project.name);
+        for bench_file in &project.bench_files {
+            transaction
+                .execute(
+                    "INSERT OR REPLACE INTO bench_files (project_id, name, source, features)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![
+                        project_id,
+                        bench_file.name,
+                        bench_file.source,
+                        bench_file.features.join(",")
+                    ],
+                )
+                .expect("Could not insert bench file");
+            for id in &bench_file.benches {
+                benchmark_id(&transaction, &project.name, &bench_file.name, id);
+            }
+        }
+        transaction.commit().unwrap();
+    }
+
+    /// Store all samples of one `data/<timestamp>` directory, replacing earlier imports of it.
+    pub fn insert_run(&mut self, run: &Path) {
+        let results = parse_run(run);
+        let transaction = self.connection.transaction().unwrap();
+        transaction
+            .execute(
+                "INSERT OR IGNORE INTO runs (name) VALUES (?1)",
+                [run_name(run)],
+            )
+            .expect("Could not insert run");
+        let run_id: i64 = transaction
+            .query_row(
+                "SELECT id FROM runs WHERE name = ?1",
+                [run_name(run)],
+                |row| row.get(0),
+            )
+            .unwrap();
+        transaction
+            .execute("DELETE FROM samples WHERE run_id = ?1", [run_id])
+            .expect("Could not delete earlier samples of run");
+
+        {
+            let mut insert = transaction
+                .prepare(
+                    "INSERT INTO samples (run_id, benchmark_id, sample, iterations, time, unit)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                )
+                .unwrap();
+            for result in &results {
+                let benchmark_id = benchmark_id(
+                    &transaction,
+                    &result.project,
+                    &result.bench_file,
+                    &result.id.full_id,
+                );
+                for sample in result.to_samples() {
+                    insert
+                        .execute(params![
+                            run_id,
+                            benchmark_id,
+                            sample.sample,
+                            sample.iterations,
+                            sample.time,
+                            sample.unit.to_string()
+                        ])
+                        .expect("Could not insert sample");
+                }
+            }
+        }
+        transaction.commit().unwrap();
+    }
+
+    pub fn insert_language_features(
+        &mut self,
+        project: &str,
+        bench_file: &str,
+        benchmark: &str,
+        recorded: &str,
+        features: &HashMap<String, u64>,
+    ) {
+        let transaction = self.connection.transaction().unwrap();
+        let benchmark_id = benchmark_id(&transaction, project, bench_file, benchmark);
+        for (feature, count) in features {
+            transaction
+                .execute(
+                    "INSERT OR REPLACE INTO language_features (benchmark_id, recorded, feature, count)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![benchmark_id, recorded, feature, *count as i64],
+                )
+                .expect("Could not insert language feature");
+        }
+        transaction.commit().unwrap();
+    }
+
+    pub fn insert_instructions(
+        &mut self,
+        project: &str,
+        bench_file: &str,
+        benchmark: &str,
+        count: u64,
+    ) {
+        let benchmark_id = benchmark_id(&self.connection, project, bench_file, benchmark);
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO instructions (benchmark_id, count) VALUES (?1, ?2)",
+                params![benchmark_id, count as i64],
+            )
+            .expect("Could not insert instruction count");
+    }
+
+    pub fn insert_statistics(&mut self, input: &Path, statistics: &[Statistic]) {
+        let input = input.to_string_lossy();
+        let transaction = self.connection.transaction().unwrap();
+        for s in statistics {
+            let benchmark_id = benchmark_id(&transaction, &s.project, &s.bench_file, &s.benchmark);
+            transaction
+                .execute(
+                    "INSERT OR REPLACE INTO statistics (input, benchmark_id, metric, samples,
+                     min, max, mean, median, q1, q3, mad, rmad, std, var, estimator, rciw_boot,
+                     rciw_bca, rciw_mjhd, unit)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16,
+                     ?17, ?18, ?19)",
+                    params![
+                        input,
+                        benchmark_id,
+                        s.metric.to_string(),
+                        s.samples,
+                        s.min,
+                        s.max,
+                        s.mean,
+                        s.median,
+                        s.q1,
+                        s.q3,
+                        s.mad,
+                        s.rmad,
+                        s.std,
+                        s.var,
+                        s.estimator,
+                        s.rciw_boot,
+                        s.rciw_bca,
+                        s.rciw_mjhd,
+                        s.unit.to_string()
+                    ],
+                )
+                .expect("Could not insert statistic");
+        }
+        transaction.commit().unwrap();
+    }
+
+    /// Store the rows `analysis` computed from `input`, replacing an earlier analysis of it.
+    pub fn insert_analysis<T: Serialize>(&mut self, analysis: &str, input: &str, rows: &[T]) {
+        let transaction = self.connection.transaction().unwrap();
+        transaction
+            .execute(
+                "DELETE FROM analyses WHERE analysis = ?1 AND input = ?2",
+                params![analysis, input],
+            )
+            .expect("Could not delete earlier analysis");
+        for (i, row) in rows.iter().enumerate() {
+            let result = serde_json::to_value(row).expect("Could not serialize row");
+            let field = |name: &str| result.get(name).and_then(Value::as_str);
+            let benchmark_id = match (field("project"), field("bench_file"), field("benchmark")) {
+                (Some(project), Some(bench_file), Some(benchmark)) => {
+                    Some(benchmark_id(&transaction, project, bench_file, benchmark))
+                }
+                _ => None,
+            };
+            transaction
+                .execute(
+                    "INSERT INTO analyses (analysis, input, row, benchmark_id, result)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![analysis, input, i, benchmark_id, result.to_string()],
+                )
+                .expect("Could not insert analysis");
+        }
+        transaction.commit().unwrap();
+    }
+
+    /// Number of rows stored for an analysis of `input`.
+    pub fn analysis_rows(&self, analysis: &str, input: &str) -> usize {
+        self.connection
+            .query_row(
+                "SELECT COUNT(*) FROM analyses WHERE analysis = ?1 AND input = ?2",
+                params![analysis, input],
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
+    /// Number of samples stored for a run, `None` if it was never imported.
+    pub fn run_samples(&self, run: &str) -> Option<usize> {
+        self.connection
+            .query_row(
+                "SELECT COUNT(samples.sample) FROM runs LEFT JOIN samples ON samples.run_id = runs.id
+                 WHERE runs.name = ?1 GROUP BY runs.id",
+                [run],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap()
+    }
+
+    /// Import the `data`, `coverage` and `instructions.csv` outputs of earlier experiments.
+    pub fn import(&mut self, data_dir: &Path, coverage_dir: &Path, instructions: &Path) {
+        if data_dir.is_dir() {
+            for run in find_runs(data_dir) {
+                println!("Importing run {}", run_name(&run));
+                self.insert_run(&run);
+            }
+        }
+
+        if coverage_dir.is_dir() {
+            let mut bench_files = HashMap::new();
+            for ((project, id), counts) in read_language_features(coverage_dir) {
+                let bench_file = bench_files
+                    .entry(project.clone())
+                    .or_insert_with(|| bench_files_by_id(&project))
+                    .get(&id)
+                    .cloned()
+                    .unwrap_or_default();
+                let counts = counts.into_iter().collect::<HashMap<String, u64>>();
+                self.insert_language_features(&project, &bench_file, &id, "imported", &counts);
+            }
+        }
+
+        if instructions.is_file() {
+            // Written by `power instructions` as `project; bench file; id; count`
+            for line in fs::read_to_string(instructions).unwrap().lines() {
+                match line.split("; ").collect::<Vec<&str>>()[..] {
+                    [project, bench_file, id, count] => match count.parse() {
+                        Ok(count) => self.insert_instructions(project, bench_file, id, count),
+                        Err(_) => println!("Invalid instruction count in line `{}`", line),
+                    },
+                    _ => println!("Skipping line `{}` of {:?}", line, instructions),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_insert_run() {
+    let dir = tempfile::tempdir().unwrap();
+    let benchmark = dir
+        .path()
+        .join("data")
+        .join("1684000000000")
+        .join("no_such_project")
+        .join("criterion")
+        .join("id")
+        .join("new");
+    fs::create_dir_all(&benchmark).unwrap();
+    fs::write(
+        benchmark.join("benchmark.json"),
+        r#"{"group_id":"id","function_id":null,"value_str":null,"throughput":null,
+            "full_id":"id","directory_name":"id","title":"id"}"#,
+    )
+    .unwrap();
+    fs::write(
+        benchmark.join("sample.json"),
+        r#"{"sampling_mode":"Flat","iters":[1.0,1.0],"times":[10.0,12.0]}"#,
+    )
+    .unwrap();
+
+    let mut store = Store::open(&dir.path().join("power.db"));
+    store.import(
+        &dir.path().join("data"),
+        &dir.path().join("coverage"),
+        &dir.path().join("instructions.csv"),
+    );
+    // Importing twice does not duplicate samples
+    store.insert_run(&dir.path().join("data").join("1684000000000"));
+    assert_eq!(store.run_samples("1684000000000"), Some(2));
+    assert_eq!(store.run_samples("1684000000001"), None);
+
+    // Samples that are gone from the run are gone from the database
+    let run = dir.path().join("data").join("1684000000000");
+    fs::remove_dir_all(run.join("no_such_project")).unwrap();
+    store.insert_run(&run);
+    assert_eq!(store.run_samples("1684000000000"), Some(0));
+}
+
+#[test]
+fn test_insert_analysis() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = Store::open(&dir.path().join("power.db"));
+    let rows = vec![
+        crate::stats::diagnostics::IterationMode {
+            project: "chrono".to_string(),
+            bench_file: "chrono".to_string(),
+            benchmark: "bench_datetime_parse".to_string(),
+            iteration: 0,
+            run: "1684000000000".to_string(),
+            median: 10.0,
+            mode: 0,
+        };
+        3
+    ];
+    store.insert_analysis("diagnostics/modes", "merged.csv", &rows);
+    // Analysing the same input again replaces the earlier rows
+    store.insert_analysis("diagnostics/modes", "merged.csv", &rows[..2]);
+    assert_eq!(store.analysis_rows("diagnostics/modes", "merged.csv"), 2);
+    assert_eq!(store.analysis_rows("diagnostics", "merged.csv"), 0);
+}
+
+#[test]
+fn test_add_unit() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("power.db");
+    // The samples table as it was created before samples had a unit
+    Connection::open(&path)
+        .unwrap()
+        .execute_batch(
+            "CREATE TABLE samples (run_id INTEGER NOT NULL, benchmark_id INTEGER NOT NULL,
+             sample INTEGER NOT NULL, iterations REAL NOT NULL, time REAL NOT NULL,
+             PRIMARY KEY (run_id, benchmark_id, sample));
+             INSERT INTO samples VALUES (1, 1, 0, 1.0, 10.0);",
+        )
+        .unwrap();
+
+    let store = Store::open(&path);
+    let unit: String = store
+        .connection
+        .query_row("SELECT unit FROM samples", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(unit, "nanoseconds");
+}